@@ -0,0 +1,48 @@
+//! Renders the same scene with an increasing number of threads and reports
+//! the speed-up over the single-threaded render.
+//!
+//! cargo run --release --example thread_scaling -- [max_threads]
+
+use std::error::Error;
+use std::time::Instant;
+
+use clap::Parser;
+use raytracer::{Config, run};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let max_threads = std::env::args()
+        .nth(1)
+        .map(|n| n.parse::<usize>())
+        .transpose()?
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+
+    let mut baseline = None;
+    let mut num_threads = 1;
+    while num_threads <= max_threads {
+        let conf = Config::parse_from([
+            "thread_scaling",
+            "--image-width", "300",
+            "--image-height", "168",
+            "--samples-per-pixel", "10",
+            "--num-threads", &num_threads.to_string(),
+            "--batch-size", "300",
+            // The same random scene for every thread count
+            "--seed", "1",
+        ]);
+
+        let now = Instant::now();
+        run(&conf)?;
+        let elapsed = now.elapsed().as_secs_f64();
+        let baseline = *baseline.get_or_insert(elapsed);
+        println!(
+            "{:>3} threads: {:>8.1} ms, speed-up {:.2}x",
+            num_threads,
+            elapsed * 1000.0,
+            baseline / elapsed,
+        );
+
+        num_threads *= 2;
+    }
+
+    Ok(())
+}
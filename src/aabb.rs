@@ -0,0 +1,98 @@
+use crate::vector::Point3;
+use crate::ray::Ray;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Self {
+        Self {min, max}
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Self {
+        Self {
+            min: Point3::new(
+                f32::min(self.min.x, other.min.x),
+                f32::min(self.min.y, other.min.y),
+                f32::min(self.min.z, other.min.z),
+            ),
+            max: Point3::new(
+                f32::max(self.max.x, other.max.x),
+                f32::max(self.max.y, other.max.y),
+                f32::max(self.max.z, other.max.z),
+            ),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.max - self.min;
+        if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        // Slab test, one axis at a time
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for axis in 0..3 {
+            let inv_d = 1.0 / r.direction[axis];
+            let mut t0 = (self.min[axis] - r.origin[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - r.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::Vec3;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn axis_parallel_rays() {
+        // Zero direction components divide to infinities
+        let towards = Ray::new(Point3::new(0.5, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let beside = Ray::new(Point3::new(2.0, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(unit_box().hit(&towards, 0.001, 100.0));
+        assert!(!unit_box().hit(&beside, 0.001, 100.0));
+    }
+
+    #[test]
+    fn respects_the_interval() {
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!unit_box().hit(&r, 0.001, 3.0));
+        assert!(!unit_box().hit(&r, 7.0, 100.0));
+        assert!(unit_box().hit(&r, 5.0, 5.5));
+    }
+
+    #[test]
+    fn surrounding_and_longest_axis() {
+        let b = unit_box().surrounding(&Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 4.0, 1.0)));
+        assert_eq!((b.min.y, b.max.y), (-1.0, 4.0));
+        assert_eq!(b.longest_axis(), 1);
+    }
+}
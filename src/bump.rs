@@ -0,0 +1,170 @@
+//! Materials that add surface detail to another material by changing the
+//! shading normal.
+
+use std::sync::Arc;
+
+use crate::objects::{forward_to_base, BsdfSample, HitRecord, Material};
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::vector::{Color, Vec3};
+
+/// Replaces the normal with one read from a tangent space normal map, where
+/// red follows +u, green +v (OpenGL convention) and blue the surface normal.
+pub struct NormalMap {
+    pub base: Arc<dyn Material + Send + Sync>,
+    /// Non-colour texture with the normals remapped to [0, 1]
+    pub map: Arc<dyn Texture + Send + Sync>,
+    /// Blend from the original normal (0) to the mapped one (1)
+    pub strength: f32,
+}
+
+impl NormalMap {
+    pub fn new(
+        base: Arc<dyn Material + Send + Sync>,
+        map: Arc<dyn Texture + Send + Sync>,
+        strength: f32,
+    ) -> Self {
+        Self {base, map, strength}
+    }
+}
+
+/// Offsets the surface along the normal by a height texture and shades with
+/// the normal of the offset surface.
+pub struct BumpMap {
+    pub base: Arc<dyn Material + Send + Sync>,
+    /// Height read from the first channel
+    pub height: Arc<dyn Texture + Send + Sync>,
+    /// World space offset for a height of 1
+    pub scale: f32,
+}
+
+impl BumpMap {
+    pub fn new(
+        base: Arc<dyn Material + Send + Sync>,
+        height: Arc<dyn Texture + Send + Sync>,
+        scale: f32,
+    ) -> Self {
+        Self {base, height, scale}
+    }
+
+    /// Steps in `u` and `v` of the finite differences, a texel for image
+    /// heights so that the slope does not depend on their resolution.
+    fn steps(&self) -> (f32, f32) {
+        match self.height.resolution() {
+            Some((width, height)) => (1.0 / width.max(1) as f32, 1.0 / height.max(1) as f32),
+            None => (DELTA, DELTA),
+        }
+    }
+}
+
+/// Step of the finite differences for heights without texels, small compared
+/// to the features of procedural textures.
+const DELTA: f32 = 0.0005;
+
+impl Material for NormalMap {
+    forward_to_base!();
+
+    fn is_cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        self.base.is_cut_out(r, rec)
+    }
+
+    fn perturb_normal(&self, rec: &mut HitRecord) {
+        self.base.perturb_normal(rec);
+        let c = self.map.value(rec.u, rec.v, &rec.p);
+        let local = Vec3::new(2.0 * c.x - 1.0, 2.0 * c.y - 1.0, (2.0 * c.z - 1.0).max(0.0));
+        let mapped = rec.shading_frame().to_world(local).normalize();
+        let n = (rec.n * (1.0 - self.strength) + mapped * self.strength).normalize();
+        if !n.near_zero() {
+            rec.n = n;
+        }
+    }
+}
+
+impl Material for BumpMap {
+    forward_to_base!();
+
+    fn is_cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        self.base.is_cut_out(r, rec)
+    }
+
+    fn perturb_normal(&self, rec: &mut HitRecord) {
+        self.base.perturb_normal(rec);
+
+        // Forward differences
+        let (du, dv) = self.steps();
+        let height = |du: f32, dv: f32| {
+            let p = rec.p + rec.dpdu * du + rec.dpdv * dv;
+            self.height.value(rec.u + du, rec.v + dv, &p).x * self.scale
+        };
+        let h = height(0.0, 0.0);
+        let dhdu = (height(du, 0.0) - h) / du;
+        let dhdv = (height(0.0, dv) - h) / dv;
+
+        // Tangents of the displaced surface, ignoring the change of the normal
+        let dpdu = rec.dpdu + rec.n * dhdu;
+        let dpdv = rec.dpdv + rec.n * dhdv;
+        let n = dpdu.cross(&dpdv).normalize();
+        if n.near_zero() || n.x.is_nan() {
+            return;
+        }
+        // Keep the bumped normal on the side of the original one
+        rec.n = if n.dot(&rec.n) < 0.0 { -n } else { n };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+    use crate::objects::Lambertian;
+    use crate::texture::{ImageTexture, SolidColor, WrapMode};
+    use crate::vector::Point3;
+
+    fn base() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    /// Hit in the middle of a square in the xy plane, facing +z, with u along
+    /// x and v along y.
+    fn perturbed(mat: Arc<dyn Material>) -> Vec3 {
+        let mut rec = HitRecord::new(Point3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, mat);
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 1.0, 0.0);
+        (rec.u, rec.v) = (0.5, 0.5);
+        rec.perturb_normal();
+        rec.n
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn constant_height_keeps_the_normal() {
+        let height = Arc::new(SolidColor::new(Color::new(0.7, 0.7, 0.7)));
+        let n = perturbed(Arc::new(BumpMap::new(base(), height, 0.3)));
+        assert_close(n, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_the_normal() {
+        let flat = Arc::new(SolidColor::new(Color::new(0.5, 0.5, 1.0)));
+        let n = perturbed(Arc::new(NormalMap::new(base(), flat, 1.0)));
+        assert_close(n, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn image_heights_step_by_a_texel() {
+        // A ramp along u tilts the normal the same at every resolution
+        for width in [8, 64, 4096] {
+            let mut image = Framebuffer::<Color>::new(width, 4);
+            for (i, pixel) in image.pixels.iter_mut().enumerate() {
+                let u = ((i % width) as f32 + 0.5) / width as f32;
+                *pixel = Color::new(u, u, u);
+            }
+            let bump = BumpMap::new(base(), Arc::new(ImageTexture::new(image, WrapMode::Clamp)), 0.1);
+            assert_eq!(bump.steps(), (1.0 / width as f32, 0.25));
+            assert_close(perturbed(Arc::new(bump)), Vec3::new(-0.1, 0.0, 1.0).normalize());
+        }
+    }
+}
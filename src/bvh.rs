@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use crate::aabb::Aabb;
+use crate::objects::{HitRecord, Hittable};
+use crate::ray::Ray;
+
+pub struct BvhNode {
+    pub left: Arc<dyn Hittable + Send + Sync>,
+    /// None for a leaf holding a single object
+    pub right: Option<Arc<dyn Hittable + Send + Sync>>,
+    pub bbox: Aabb,
+}
+
+impl BvhNode {
+    /// Builds a hierarchy over `objects`, which must all have a bounding box
+    /// and must not be empty.
+    pub fn new(objects: &mut [Arc<dyn Hittable + Send + Sync>]) -> Self {
+        let boxes = |o: &Arc<dyn Hittable + Send + Sync>| {
+            o.bounding_box().expect("BVH objects must have a bounding box")
+        };
+
+        // Split along the longest axis of the centroids
+        let centroids = objects.iter()
+            .map(|o| {
+                let c = boxes(o).centroid();
+                Aabb::new(c, c)
+            })
+            .reduce(|a, b| a.surrounding(&b))
+            .expect("BVH cannot be built from an empty list");
+        let axis = centroids.longest_axis();
+
+        let (left, right): (Arc<dyn Hittable + Send + Sync>, Option<Arc<dyn Hittable + Send + Sync>>) =
+            match objects.len() {
+                1 => (Arc::clone(&objects[0]), None),
+                2 => (Arc::clone(&objects[0]), Some(Arc::clone(&objects[1]))),
+                n => {
+                    let mid = n / 2;
+                    objects.select_nth_unstable_by(mid, |a, b| {
+                        boxes(a).centroid()[axis].total_cmp(&boxes(b).centroid()[axis])
+                    });
+                    let (lo, hi) = objects.split_at_mut(mid);
+                    (Arc::new(BvhNode::new(lo)), Some(Arc::new(BvhNode::new(hi))))
+                },
+            };
+
+        let bbox = match &right {
+            Some(right) => boxes(&left).surrounding(&boxes(right)),
+            None => boxes(&left),
+        };
+        Self {left, right, bbox}
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        // Only look for hits on the right closer than the left one
+        let hit_left = self.left.hit(r, t_min, t_max);
+        let right = match &self.right {
+            Some(right) => right,
+            None => return hit_left,
+        };
+        let t_max = hit_left.as_ref().map_or(t_max, |rec| rec.t);
+        let hit_right = right.hit(r, t_min, t_max);
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::objects::{Lambertian, Scene, Sphere};
+    use crate::rng::SampleRng;
+    use crate::vector::{Color, Point3, Vec3};
+
+    fn random_spheres(n: usize, rng: &mut SampleRng) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        (0..n)
+            .map(|_| -> Arc<dyn Hittable + Send + Sync> {
+                Arc::new(Sphere {
+                    centre: Vec3::random(-5.0, 5.0, rng),
+                    radius: rng.gen_range(0.1..1.0),
+                    mat: mat.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Closest hits through the BVH match a linear scan over the objects.
+    fn check_against_linear(objects: Vec<Arc<dyn Hittable + Send + Sync>>, rng: &mut SampleRng) {
+        let linear = Scene::new(objects.clone());
+        let mut accelerated = Scene::new(objects);
+        accelerated.build_bvh();
+        for _ in 0..2000 {
+            let r = Ray::new(Vec3::random(-8.0, 8.0, rng), Vec3::unit_random(rng));
+            let expected = linear.hit(&r, 0.001, 100.0);
+            let found = accelerated.hit(&r, 0.001, 100.0);
+            match (expected, found) {
+                (None, None) => {},
+                (Some(expected), Some(found)) => {
+                    assert_eq!(expected.t, found.t);
+                    assert_eq!(expected.object, found.object);
+                },
+                (expected, found) => panic!("linear hit {}, BVH hit {}", expected.is_some(), found.is_some()),
+            }
+        }
+    }
+
+    #[test]
+    fn matches_linear_scan() {
+        let mut rng = SampleRng::seeded(3);
+        for n in [0, 1, 2, 3, 7, 100] {
+            let objects = random_spheres(n, &mut rng);
+            check_against_linear(objects, &mut rng);
+        }
+    }
+
+    #[test]
+    fn single_object_is_a_leaf() {
+        let mut rng = SampleRng::seeded(5);
+        let mut objects = random_spheres(1, &mut rng);
+        let node = BvhNode::new(&mut objects);
+        assert!(node.right.is_none());
+        let sphere = objects[0].bounding_box().unwrap();
+        assert_eq!(node.bbox.centroid().x, sphere.centroid().x);
+
+        let centre = sphere.centroid();
+        let r = Ray::new(centre + Point3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = node.hit(&r, 0.001, 100.0).expect("ray towards the centre hits");
+        assert_eq!(rec.t, objects[0].hit(&r, 0.001, 100.0).unwrap().t);
+        assert!(node.hit(&Ray::new(r.origin, Vec3::new(0.0, 0.0, 1.0)), 0.001, 100.0).is_none());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::utilities::deg2rad;
 use crate::vector::{Point3, Vec3};
 use crate::ray::Ray;
+use crate::sampling;
 
 pub struct Camera {
     pub origin: Point3,
@@ -42,8 +43,9 @@ impl Camera {
         Self {origin, lower_left_corner, horizontal, vertical, u, v, w, lens_radius}
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        let rd = Vec3::unit_disk_random() * self.lens_radius;
+    /// Ray through film position `(u, v)`, `lens` picks the point on the lens.
+    pub fn get_ray(&self, u: f32, v: f32, lens: [f32; 2]) -> Ray {
+        let rd = sampling::concentric_disk(lens) * self.lens_radius;
         let offset = self.u * rd.x + self.v * rd.y;
         Ray::new(
             self.origin + offset,
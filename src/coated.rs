@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use crate::microfacet::{fresnel_dielectric, TrowbridgeReitz};
+use crate::objects::{BsdfSample, HitRecord, Material};
+use crate::sampling;
+use crate::texture::{SolidColor, Texture};
+use crate::utilities::PI;
+use crate::vector::{Color, Vec3};
+
+/// Diffuse base under a clear dielectric coat, for plastics and varnish.
+/// The coat reflects by Fresnel, smooth or GGX rough, and the rest enters and
+/// bounces around the base before leaving, as in Weidlich and Wilkie's
+/// "Arbitrarily Layered Micro-Facet Surfaces" (2007) with a flat interface
+/// for the diffuse part.
+pub struct CoatedDiffuse {
+    pub albedo: Arc<dyn Texture + Send + Sync>,
+    /// Roughness of the coat, read from the first channel
+    pub roughness: Arc<dyn Texture + Send + Sync>,
+    index: f32,
+    /// Fraction of the diffuse light the coat reflects back down
+    internal_reflectance: f32,
+}
+
+impl CoatedDiffuse {
+    pub fn new(albedo: Color, index: f32, roughness: f32) -> Self {
+        Self::textured(
+            Arc::new(SolidColor::new(albedo)),
+            index,
+            Arc::new(SolidColor::new(Color::new(roughness, roughness, roughness))),
+        )
+    }
+
+    pub fn textured(
+        albedo: Arc<dyn Texture + Send + Sync>,
+        index: f32,
+        roughness: Arc<dyn Texture + Send + Sync>,
+    ) -> Self {
+        let internal_reflectance = diffuse_fresnel(1.0 / index);
+        Self {albedo, roughness, index, internal_reflectance}
+    }
+
+    /// Refractive index of the coat.
+    pub fn index(&self) -> f32 {
+        self.index
+    }
+
+    fn distribution(&self, rec: &HitRecord) -> TrowbridgeReitz {
+        let alpha = TrowbridgeReitz::roughness_to_alpha(self.roughness.value(rec.u, rec.v, &rec.p).x);
+        TrowbridgeReitz::new(alpha, alpha)
+    }
+
+    /// Light that went through the coat twice, for local directions above
+    /// the surface.
+    fn diffuse(&self, wo: &Vec3, wi: &Vec3, albedo: &Color) -> Color {
+        let transmitted = (1.0 - fresnel_dielectric(wo.z, self.index))
+            * (1.0 - fresnel_dielectric(wi.z, self.index))
+            / (self.index * self.index);
+        let bounces = |a: f32| a / (PI * (1.0 - a * self.internal_reflectance));
+        Color::new(bounces(albedo.x), bounces(albedo.y), bounces(albedo.z)) * transmitted
+    }
+
+    /// Probability of sampling the coat rather than the base.
+    fn coat_probability(&self, wo: &Vec3, albedo: &Color) -> f32 {
+        let coat = fresnel_dielectric(wo.z, self.index);
+        let base = (1.0 - coat) * (albedo.x + albedo.y + albedo.z) / 3.0;
+        if coat + base > 0.0 { coat / (coat + base) } else { 1.0 }
+    }
+
+    /// Value and density of the non-specular lobes.
+    fn eval_pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> (Color, f32) {
+        let black = Color::new(0.0, 0.0, 0.0);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (black, 0.0);
+        }
+        let albedo = self.albedo.value(rec.u, rec.v, &rec.p);
+        let p_coat = self.coat_probability(wo, &albedo);
+        let mut f = self.diffuse(wo, wi, &albedo);
+        let mut pdf = (1.0 - p_coat) * sampling::cosine_hemisphere_pdf(wi.z);
+
+        let distrib = self.distribution(rec);
+        if !distrib.is_smooth() {
+            let wm = (*wo + *wi).normalize();
+            let fresnel = fresnel_dielectric(wo.dot(&wm), self.index);
+            let spec = distrib.d(&wm) * distrib.g(wo, wi) * fresnel / (4.0 * wo.z * wi.z);
+            f = f + Color::new(spec, spec, spec);
+            pdf += p_coat * distrib.pdf(wo, &wm) / (4.0 * wo.dot(&wm));
+        }
+        (f, pdf)
+    }
+}
+
+/// Cosine weighted average of the Fresnel reflectance over the hemisphere.
+fn diffuse_fresnel(eta: f32) -> f32 {
+    const STEPS: usize = 256;
+    (0..STEPS).map(|i| {
+        let cos_theta = (i as f32 + 0.5) / STEPS as f32;
+        2.0 * cos_theta * fresnel_dielectric(cos_theta, eta)
+    }).sum::<f32>() / STEPS as f32
+}
+
+impl Material for CoatedDiffuse {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color {
+        let frame = rec.shading_frame();
+        self.eval_pdf(&frame.to_local(*wo), &frame.to_local(*wi), rec).0
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32 {
+        let frame = rec.shading_frame();
+        self.eval_pdf(&frame.to_local(*wo), &frame.to_local(*wi), rec).1
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, uc: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        let frame = rec.shading_frame();
+        let wo_local = frame.to_local(*wo);
+        if wo_local.z <= 0.0 {
+            return None;
+        }
+        let albedo = self.albedo.value(rec.u, rec.v, &rec.p);
+        let p_coat = self.coat_probability(&wo_local, &albedo);
+        let distrib = self.distribution(rec);
+
+        let wi = if uc < p_coat {
+            if distrib.is_smooth() {
+                let wi = Vec3::new(-wo_local.x, -wo_local.y, wo_local.z);
+                let f = fresnel_dielectric(wo_local.z, self.index) / wo_local.z;
+                let wi = frame.to_world(wi);
+                return Some(BsdfSample {wi, f: Color::new(f, f, f), pdf: p_coat, specular: true});
+            }
+            let wm = distrib.sample_wm(&wo_local, u);
+            wm * (2.0 * wo_local.dot(&wm)) - wo_local
+        } else {
+            sampling::cosine_hemisphere(u)
+        };
+
+        let (f, pdf) = self.eval_pdf(&wo_local, &wi, rec);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {wi: frame.to_world(wi), f, pdf, specular: false})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::check_bsdf;
+
+    #[test]
+    fn smooth_coat_is_consistent() {
+        check_bsdf(Arc::new(CoatedDiffuse::new(Color::new(1.0, 1.0, 1.0), 1.5, 0.0)));
+    }
+
+    #[test]
+    fn rough_coat_is_consistent() {
+        check_bsdf(Arc::new(CoatedDiffuse::new(Color::new(0.9, 0.2, 0.1), 1.5, 0.4)));
+    }
+}
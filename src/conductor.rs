@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use serde::Deserialize;
+
+use crate::microfacet::{fresnel_conductor, TrowbridgeReitz};
+use crate::objects::{BsdfSample, HitRecord, Material};
+use crate::texture::{SolidColor, Texture};
+use crate::vector::{Color, Vec3};
+
+/// Measured metals, complex refractive indices at the red, green and blue
+/// wavelengths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetalPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+    Iron,
+}
+
+impl MetalPreset {
+    /// Real and imaginary parts of the index.
+    pub fn eta_k(&self) -> (Color, Color) {
+        let (eta, k) = match self {
+            MetalPreset::Gold => ([0.143, 0.374, 1.442], [3.983, 2.386, 1.603]),
+            MetalPreset::Copper => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+            MetalPreset::Aluminium => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            MetalPreset::Silver => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+            MetalPreset::Iron => ([2.911, 2.950, 2.585], [3.089, 2.932, 2.767]),
+        };
+        (Color::from(eta), Color::from(k))
+    }
+}
+
+/// Cook-Torrance metal with a GGX distribution, possibly anisotropic, and
+/// the exact conductor Fresnel term. Unlike `Metal` it has a proper density,
+/// so rough conductors get light sampling too.
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    /// Roughness along `dpdu` and `dpdv`, read from the first channel
+    pub roughness_u: Arc<dyn Texture + Send + Sync>,
+    pub roughness_v: Arc<dyn Texture + Send + Sync>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f32) -> Self {
+        let roughness: Arc<dyn Texture + Send + Sync> =
+            Arc::new(SolidColor::new(Color::new(roughness, roughness, roughness)));
+        Self::textured(eta, k, Arc::clone(&roughness), roughness)
+    }
+
+    pub fn preset(metal: MetalPreset, roughness: f32) -> Self {
+        let (eta, k) = metal.eta_k();
+        Self::new(eta, k, roughness)
+    }
+
+    pub fn textured(
+        eta: Color,
+        k: Color,
+        roughness_u: Arc<dyn Texture + Send + Sync>,
+        roughness_v: Arc<dyn Texture + Send + Sync>,
+    ) -> Self {
+        Self {eta, k, roughness_u, roughness_v}
+    }
+
+    fn distribution(&self, rec: &HitRecord) -> TrowbridgeReitz {
+        let alpha = |t: &Arc<dyn Texture + Send + Sync>| {
+            TrowbridgeReitz::roughness_to_alpha(t.value(rec.u, rec.v, &rec.p).x)
+        };
+        TrowbridgeReitz::new(alpha(&self.roughness_u), alpha(&self.roughness_v))
+    }
+
+    /// BSDF for local directions on the same side as the normal.
+    fn eval_local(&self, wo: &Vec3, wi: &Vec3, distrib: &TrowbridgeReitz) -> Color {
+        let wm = *wo + *wi;
+        if wm.near_zero() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let wm = wm.normalize();
+        let fresnel = fresnel_conductor(wo.dot(&wm).abs(), &self.eta, &self.k);
+        fresnel * (distrib.d(&wm) * distrib.g(wo, wi) / (4.0 * wo.z * wi.z))
+    }
+}
+
+impl Material for Conductor {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color {
+        let frame = rec.shading_frame();
+        let (wo, wi) = (frame.to_local(*wo), frame.to_local(*wi));
+        let distrib = self.distribution(rec);
+        if wo.z <= 0.0 || wi.z <= 0.0 || distrib.is_smooth() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.eval_local(&wo, &wi, &distrib)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32 {
+        let frame = rec.shading_frame();
+        let (wo, wi) = (frame.to_local(*wo), frame.to_local(*wi));
+        let distrib = self.distribution(rec);
+        if wo.z <= 0.0 || wi.z <= 0.0 || distrib.is_smooth() {
+            return 0.0;
+        }
+        let wm = wo + wi;
+        if wm.near_zero() {
+            return 0.0;
+        }
+        let wm = wm.normalize();
+        distrib.pdf(&wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, _uc: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        let frame = rec.shading_frame();
+        let wo_local = frame.to_local(*wo);
+        if wo_local.z <= 0.0 {
+            return None;
+        }
+        let distrib = self.distribution(rec);
+
+        if distrib.is_smooth() {
+            let wi = frame.to_world(Vec3::new(-wo_local.x, -wo_local.y, wo_local.z));
+            let f = fresnel_conductor(wo_local.z, &self.eta, &self.k) / wo_local.z;
+            return Some(BsdfSample {wi, f, pdf: 1.0, specular: true});
+        }
+
+        // Mirror `wo` about a visible microfacet normal
+        let wm = distrib.sample_wm(&wo_local, u);
+        let wi = wm * (2.0 * wo_local.dot(&wm)) - wo_local;
+        if wi.z <= 0.0 {
+            return None;
+        }
+        let pdf = distrib.pdf(&wo_local, &wm) / (4.0 * wo_local.dot(&wm).abs());
+        if pdf <= 0.0 {
+            return None;
+        }
+        let f = self.eval_local(&wo_local, &wi, &distrib);
+        Some(BsdfSample {wi: frame.to_world(wi), f, pdf, specular: false})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::check_bsdf;
+
+    #[test]
+    fn gold_is_consistent() {
+        check_bsdf(Arc::new(Conductor::preset(MetalPreset::Gold, 0.3)));
+    }
+
+    #[test]
+    fn rough_silver_is_consistent() {
+        check_bsdf(Arc::new(Conductor::preset(MetalPreset::Silver, 0.8)));
+    }
+
+    #[test]
+    fn anisotropic_iron_is_consistent() {
+        let (eta, k) = MetalPreset::Iron.eta_k();
+        let roughness = |x: f32| Arc::new(SolidColor::new(Color::new(x, x, x)));
+        check_bsdf(Arc::new(Conductor::textured(eta, k, roughness(0.6), roughness(0.15))));
+    }
+}
@@ -0,0 +1,292 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::framebuffer::Framebuffer;
+use crate::hdr;
+use crate::ray::Ray;
+use crate::sky::PhysicalSky;
+use crate::utilities::{deg2rad, PI};
+use crate::vector::{Color, Vec3};
+
+/// Radiance arriving from directions that do not hit any object.
+pub enum Background {
+    Solid(Color),
+    /// Blend along the vertical direction, from straight down to straight up
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    Map(EnvironmentMap),
+    /// Analytic daylight, the sun itself is a separate light
+    Physical(PhysicalSky),
+}
+
+impl Background {
+    /// The default white to blue sky.
+    pub fn sky() -> Self {
+        Background::Gradient {
+            bottom: Color::new(1.0, 1.0, 1.0),
+            top: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+
+    pub fn color(&self, r: &Ray) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient {bottom, top} => {
+                let t = 0.5 * (r.direction.normalize().y + 1.0);
+                *bottom * (1.0 - t) + *top * t
+            },
+            Background::Map(map) => map.radiance(&r.direction),
+            Background::Physical(sky) => sky.radiance(&r.direction),
+        }
+    }
+
+    /// Whether the background is worth sampling as a light.
+    pub fn is_sampled(&self) -> bool {
+        matches!(self, Background::Map(_))
+    }
+
+    /// Direction towards the background, picked by brightness.
+    pub fn sample(&self, u: [f32; 2]) -> Option<Vec3> {
+        match self {
+            Background::Map(map) => map.sample(u),
+            _ => None,
+        }
+    }
+
+    /// Solid angle density of `sample` returning `direction`.
+    pub fn pdf(&self, direction: &Vec3) -> f32 {
+        match self {
+            Background::Map(map) => map.pdf(direction),
+            _ => 0.0,
+        }
+    }
+}
+
+/// Equirectangular environment map, +y is up and the centre of the image
+/// looks along -x before rotating.
+pub struct EnvironmentMap {
+    image: Framebuffer<Color>,
+    /// Rotation around +y in radians
+    rotation: f32,
+    intensity: f32,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// `rotation` is in degrees around the vertical axis.
+    pub fn new(image: Framebuffer<Color>, rotation: f32, intensity: f32) -> Self {
+        // Pixels near the poles cover less solid angle
+        let (width, height) = (image.width, image.height);
+        let func: Vec<f32> = image.pixels.iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let sin_theta = f32::sin(PI * ((i / width) as f32 + 0.5) / height as f32);
+                luminance(c) * sin_theta
+            })
+            .collect();
+        let distribution = Distribution2D::new(&func, width, height);
+        Self {image, rotation: deg2rad(rotation), intensity, distribution}
+    }
+
+    pub fn load(path: &Path, rotation: f32, intensity: f32) -> Result<Self, Box<dyn Error>> {
+        let image = hdr::load_hdr(path)?;
+        if image.pixels.is_empty() {
+            return Err(format!("{}: empty environment map", path.display()).into());
+        }
+        Ok(Self::new(image, rotation, intensity))
+    }
+
+    fn direction_to_uv(&self, direction: &Vec3) -> [f32; 2] {
+        let d = direction.normalize();
+        let phi = d.z.atan2(d.x) - self.rotation;
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        [u, v]
+    }
+
+    fn uv_to_direction(&self, [u, v]: [f32; 2]) -> Vec3 {
+        let theta = v * PI;
+        let phi = u * 2.0 * PI + self.rotation;
+        Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+    }
+
+    /// Bilinear lookup, wrapping around horizontally.
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        let [u, v] = self.direction_to_uv(direction);
+        let (width, height) = (self.image.width, self.image.height);
+        let x = u * width as f32 - 0.5;
+        let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let pixel = |x: i64, y: usize| {
+            let x = x.rem_euclid(width as i64) as usize;
+            self.image.pixels[y.min(height - 1) * width + x]
+        };
+        let (x0, y0) = (x0 as i64, y0 as usize);
+        let top = pixel(x0, y0) * (1.0 - tx) + pixel(x0 + 1, y0) * tx;
+        let bottom = pixel(x0, y0 + 1) * (1.0 - tx) + pixel(x0 + 1, y0 + 1) * tx;
+        (top * (1.0 - ty) + bottom * ty) * self.intensity
+    }
+
+    pub fn sample(&self, u: [f32; 2]) -> Option<Vec3> {
+        let (uv, pdf) = self.distribution.sample(u);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(self.uv_to_direction(uv))
+    }
+
+    pub fn pdf(&self, direction: &Vec3) -> f32 {
+        let uv = self.direction_to_uv(direction);
+        let sin_theta = f32::sin(uv[1] * PI);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // Change of variables from the image to the sphere
+        self.distribution.pdf(uv) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+fn luminance(c: &Color) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Piecewise constant density over [0, 1).
+struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    fn new(func: &[f32]) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0) / n as f32;
+        }
+        let integral = cdf[n];
+        // Fall back to uniform if everything is black
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 { *c / integral } else { i as f32 / n as f32 };
+        }
+        Self {func: func.iter().map(|f| f.max(0.0)).collect(), cdf, integral}
+    }
+
+    fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0.0 { self.func[index] / self.integral } else { 1.0 }
+    }
+
+    /// Returns the sample, its density and the piece it fell in.
+    fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.func.len();
+        let index = self.cdf.partition_point(|&c| c <= u).clamp(1, n) - 1;
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let du = if width > 0.0 { (u - self.cdf[index]) / width } else { 0.0 };
+        let x = ((index as f32 + du) / n as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf(index), index)
+    }
+}
+
+/// Piecewise constant density over [0, 1)^2, rows picked first.
+struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = func.chunks(width).map(Distribution1D::new).collect();
+        let marginal = Distribution1D::new(&rows.iter().map(|r| r.integral).collect::<Vec<_>>());
+        debug_assert_eq!(rows.len(), height);
+        Self {rows, marginal}
+    }
+
+    fn sample(&self, u: [f32; 2]) -> ([f32; 2], f32) {
+        let (v, pdf_v, row) = self.marginal.sample(u[1]);
+        let (u, pdf_u, _) = self.rows[row].sample(u[0]);
+        ([u, v], pdf_u * pdf_v)
+    }
+
+    fn pdf(&self, [u, v]: [f32; 2]) -> f32 {
+        let height = self.rows.len();
+        let width = self.rows[0].func.len();
+        let row = ((v * height as f32) as usize).min(height - 1);
+        let column = ((u * width as f32) as usize).min(width - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::rng::SampleRng;
+    use crate::sampling::{uniform_sphere, uniform_sphere_pdf};
+
+    /// Map with a few bright pixels over a dim background, rotated so that
+    /// the rotation is part of what is checked.
+    fn map() -> EnvironmentMap {
+        let mut image = Framebuffer::<Color>::new(16, 8);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            let v = 0.1 + (i % 5) as f32 * 0.05;
+            *pixel = Color::new(v, v, v);
+        }
+        image.pixels[2 * 16 + 3] = Color::new(20.0, 10.0, 5.0);
+        image.pixels[5 * 16 + 12] = Color::new(0.0, 8.0, 0.0);
+        EnvironmentMap::new(image, 30.0, 1.0)
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let map = map();
+        let mut rng = SampleRng::seeded(11);
+        let n = 200_000;
+        let integral: f32 = (0..n)
+            .map(|_| map.pdf(&uniform_sphere([rng.gen(), rng.gen()])) / uniform_sphere_pdf())
+            .sum::<f32>() / n as f32;
+        assert!((integral - 1.0).abs() < 0.02, "pdf integrates to {}", integral);
+    }
+
+    #[test]
+    fn pdf_matches_the_sampled_density() {
+        let map = map();
+        let mut rng = SampleRng::seeded(12);
+        for _ in 0..1000 {
+            let u = [rng.gen(), rng.gen()];
+            let (uv, pdf_uv) = map.distribution.sample(u);
+            let direction = map.sample(u).unwrap();
+            let expected = pdf_uv / (2.0 * PI * PI * f32::sin(uv[1] * PI));
+            let pdf = map.pdf(&direction);
+            assert!((pdf - expected).abs() <= 1e-3 * expected, "pdf {} for {}", pdf, expected);
+        }
+    }
+
+    #[test]
+    fn pixels_are_sampled_by_luminance() {
+        let map = map();
+        let (width, height) = (map.image.width, map.image.height);
+        let mut counts = vec![0usize; width * height];
+        let mut rng = SampleRng::seeded(13);
+        let n = 200_000;
+        for _ in 0..n {
+            let [u, v] = map.direction_to_uv(&map.sample([rng.gen(), rng.gen()]).unwrap());
+            let x = ((u * width as f32) as usize).min(width - 1);
+            let y = ((v * height as f32) as usize).min(height - 1);
+            counts[y * width + x] += 1;
+        }
+
+        // Luminance weighted by the solid angle of the pixel
+        let weights: Vec<f32> = map.image.pixels.iter()
+            .enumerate()
+            .map(|(i, c)| luminance(c) * f32::sin(PI * ((i / width) as f32 + 0.5) / height as f32))
+            .collect();
+        let total: f32 = weights.iter().sum();
+        for (i, (&count, &weight)) in counts.iter().zip(weights.iter()).enumerate() {
+            let (observed, expected) = (count as f32 / n as f32, weight / total);
+            assert!((observed - expected).abs() < 0.1 * expected + 2e-4, "pixel {}: {} for {}", i, observed, expected);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::{self, Write};
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExrPixelType {
+    /// 16-bit floats
+    Half,
+    /// 32-bit floats
+    Float,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExrCompression {
+    None,
+    /// Deflate over blocks of 16 scanlines
+    Zip,
+}
+
+impl ExrCompression {
+    fn lines_per_block(&self) -> usize {
+        match self {
+            ExrCompression::None => 1,
+            ExrCompression::Zip => 16,
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            ExrCompression::None => 0,
+            ExrCompression::Zip => 3,
+        }
+    }
+}
+
+/// One image plane, `data` is row-major with the top row first.
+pub struct ExrChannel<'a> {
+    pub name: String,
+    pub pixel_type: ExrPixelType,
+    pub data: &'a [f32],
+}
+
+/// Writes a single-part scanline OpenEXR file. Channels may come in any
+/// order, they are sorted by name as the format requires.
+pub fn write_exr<W: Write>(
+    w: &mut W,
+    width: usize,
+    height: usize,
+    channels: &[ExrChannel],
+    compression: ExrCompression,
+) -> io::Result<()> {
+    let mut channels: Vec<&ExrChannel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    if let Some(c) = channels.iter().find(|c| c.data.len() != width * height) {
+        let message = format!("channel '{}' has {} values for {}x{} pixels", c.name, c.data.len(), width, height);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    // Magic number and version 2, single-part scanline
+    w.write_all(&[0x76, 0x2f, 0x31, 0x01])?;
+    w.write_all(&2u32.to_le_bytes())?;
+
+    // Header
+    let mut chlist = Vec::new();
+    for channel in channels.iter() {
+        chlist.extend_from_slice(channel.name.as_bytes());
+        chlist.push(0);
+        let pixel_type: i32 = match channel.pixel_type {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        };
+        chlist.extend_from_slice(&pixel_type.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    let mut header = Vec::new();
+    write_attribute(&mut header, "channels", "chlist", &chlist);
+    write_attribute(&mut header, "compression", "compression", &[compression.id()]);
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+    w.write_all(&header)?;
+
+    // Encode every block first, the offset table comes before them
+    let lines = compression.lines_per_block();
+    let blocks: Vec<Vec<u8>> = (0..height)
+        .step_by(lines)
+        .map(|y0| {
+            let mut raw = Vec::new();
+            for y in y0..usize::min(y0 + lines, height) {
+                for channel in channels.iter() {
+                    let row = &channel.data[y * width..(y + 1) * width];
+                    for &v in row {
+                        match channel.pixel_type {
+                            ExrPixelType::Half => raw.extend_from_slice(&f32_to_f16(v).to_le_bytes()),
+                            ExrPixelType::Float => raw.extend_from_slice(&v.to_le_bytes()),
+                        }
+                    }
+                }
+            }
+            match compression {
+                ExrCompression::None => raw,
+                ExrCompression::Zip => zip_compress(raw),
+            }
+        })
+        .collect();
+
+    let table_size = 8 * blocks.len();
+    let mut offset = (8 + header.len() + table_size) as u64;
+    for block in blocks.iter() {
+        w.write_all(&offset.to_le_bytes())?;
+        offset += 8 + block.len() as u64;
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        w.write_all(&((i * lines) as i32).to_le_bytes())?;
+        w.write_all(&(block.len() as i32).to_le_bytes())?;
+        w.write_all(block)?;
+    }
+    Ok(())
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn zip_compress(raw: Vec<u8>) -> Vec<u8> {
+    // Split even and odd bytes, then store differences between neighbours
+    let half = raw.len().div_ceil(2);
+    let mut tmp = vec![0u8; raw.len()];
+    for (i, &b) in raw.iter().enumerate() {
+        let dst = if i % 2 == 0 { i / 2 } else { half + i / 2 };
+        tmp[dst] = b;
+    }
+    for i in (1..tmp.len()).rev() {
+        tmp[i] = tmp[i].wrapping_sub(tmp[i - 1]).wrapping_add(128);
+    }
+
+    // Blocks that do not shrink are stored as they are
+    let data = miniz_oxide::deflate::compress_to_vec_zlib(&tmp, 6);
+    if data.len() < raw.len() { data } else { raw }
+}
+
+/// Converts to IEEE 754 half precision, rounding to nearest even.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    // Infinity and NaN
+    if exp == 0xff {
+        let nan = if mantissa != 0 { 0x0200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let exp = exp - 127 + 15;
+    if exp >= 0x1f {
+        // Overflow to infinity
+        sign | 0x7c00
+    } else if exp <= 0 {
+        // Subnormal or zero
+        if exp < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exp) as u32;
+        let half = mantissa >> shift;
+        let rem = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = rem > halfway || (rem == halfway && half & 1 == 1);
+        sign | (half + round as u32) as u16
+    } else {
+        let half = ((exp as u32) << 10) | (mantissa >> 13);
+        let rem = mantissa & 0x1fff;
+        let round = rem > 0x1000 || (rem == 0x1000 && half & 1 == 1);
+        // A carry out of the mantissa correctly bumps the exponent
+        sign | (half + round as u32) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_normal_values() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    }
+
+    #[test]
+    fn half_rounds_to_nearest_even() {
+        // Halfway between 1 and the next half, 1 + 2^-10, goes to the even 1
+        assert_eq!(f32_to_f16(1.0 + f32::powi(2.0, -11)), 0x3c00);
+        // Halfway above an odd mantissa goes up to the even one
+        assert_eq!(f32_to_f16(1.0 + 3.0 * f32::powi(2.0, -11)), 0x3c02);
+        // Just above halfway goes up
+        assert_eq!(f32_to_f16(1.0 + f32::powi(2.0, -11) + f32::powi(2.0, -20)), 0x3c01);
+        // Rounding up the largest mantissa carries into the exponent
+        assert_eq!(f32_to_f16(2.0 - f32::powi(2.0, -12)), 0x4000);
+    }
+
+    #[test]
+    fn half_subnormals() {
+        let smallest = f32::powi(2.0, -24);
+        assert_eq!(f32_to_f16(smallest), 0x0001);
+        assert_eq!(f32_to_f16(-smallest), 0x8001);
+        assert_eq!(f32_to_f16(1023.0 * smallest), 0x03ff);
+        // Half of the smallest subnormal rounds to even, which is zero
+        assert_eq!(f32_to_f16(0.5 * smallest), 0x0000);
+        assert_eq!(f32_to_f16(1.5 * smallest), 0x0002);
+        assert_eq!(f32_to_f16(0.25 * smallest), 0x0000);
+        // The largest subnormal rounds up to the smallest normal
+        assert_eq!(f32_to_f16(1023.5 * smallest), 0x0400);
+    }
+
+    #[test]
+    fn half_overflow_and_nan() {
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(1e10), 0x7c00);
+        assert_eq!(f32_to_f16(-1e10), 0xfc00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        let nan = f32_to_f16(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+    }
+
+    #[test]
+    fn mismatched_channels_are_an_error() {
+        let data = [0.0; 5];
+        let channels = [ExrChannel {name: "R".into(), pixel_type: ExrPixelType::Half, data: &data}];
+        let error = write_exr(&mut vec![], 2, 2, &channels, ExrCompression::None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
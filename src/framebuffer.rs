@@ -0,0 +1,69 @@
+use clap::ValueEnum;
+use rayon::prelude::*;
+
+use crate::vector::{Color, Vec3};
+
+/// Image storage in row-major order, top row first. Render tasks borrow
+/// disjoint batches of pixels, so no locking is needed while rendering.
+pub struct Framebuffer<T> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<T>,
+}
+
+impl<T: Clone + Default + Send> Framebuffer<T> {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {width, height, pixels: vec![T::default(); width * height]}
+    }
+
+    /// Splits the image into batches of `batch_size` consecutive pixels. Each
+    /// item carries the linear index of its first pixel.
+    pub fn par_batches_mut(
+        &mut self,
+        batch_size: usize,
+    ) -> impl IndexedParallelIterator<Item = (usize, &mut [T])> {
+        self.pixels
+            .par_chunks_mut(batch_size)
+            .enumerate()
+            .map(move |(i, batch)| (i * batch_size, batch))
+    }
+}
+
+impl<T> Framebuffer<T> {
+    pub fn map<U>(&self, f: impl Fn(&T) -> U) -> Framebuffer<U> {
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(f).collect(),
+        }
+    }
+}
+
+impl<T> IntoIterator for Framebuffer<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pixels.into_iter()
+    }
+}
+
+/// Extra outputs taken from the first hit of each pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Aov {
+    /// Distance from the camera, infinite for the background
+    Depth,
+    /// World space shading normal, zero for the background
+    Normal,
+}
+
+/// Beauty pass plus the optional extra layers requested for compositing.
+pub struct RenderLayers {
+    pub beauty: Framebuffer<Color>,
+    pub depth: Option<Framebuffer<f32>>,
+    pub normal: Option<Framebuffer<Vec3>>,
+    /// Seed the render used, picked at random unless given in the config
+    pub seed: u64,
+    /// Problems in the scene that were worked around
+    pub warnings: Vec<String>,
+}
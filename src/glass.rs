@@ -0,0 +1,238 @@
+//! Rough and thin-walled glass, and the absorption shared with `Dielectric`.
+
+use std::sync::Arc;
+
+use crate::microfacet::{fresnel_dielectric, refract, TrowbridgeReitz};
+use crate::objects::{BsdfSample, HitRecord, Material};
+use crate::texture::{SolidColor, Texture};
+use crate::vector::{Color, Vec3};
+
+/// Beer-Lambert absorption coefficient that leaves `tint` after `distance`.
+pub fn absorption_for_tint(tint: &Color, distance: f32) -> Color {
+    let coefficient = |c: f32| -c.clamp(1e-4, 1.0).ln() / distance.max(1e-4);
+    Color::new(coefficient(tint.x), coefficient(tint.y), coefficient(tint.z))
+}
+
+/// Fraction of light left after `distance` through a medium.
+pub fn beer_lambert(absorption: &Color, distance: f32) -> Color {
+    Color::new(
+        (-absorption.x * distance).exp(),
+        (-absorption.y * distance).exp(),
+        (-absorption.z * distance).exp(),
+    )
+}
+
+/// Frosted glass, GGX microfacets that both reflect and refract (Walter et
+/// al., "Microfacet Models for Refraction through Rough Surfaces", 2007).
+/// Radiance is not scaled by the squared index ratio on refraction, like
+/// `Dielectric`, which cancels out for closed objects.
+pub struct RoughDielectric {
+    pub index: f32,
+    /// Read from the first channel, zero gives smooth glass
+    pub roughness: Arc<dyn Texture + Send + Sync>,
+    /// Beer-Lambert absorption coefficient per unit distance
+    pub absorption: Color,
+}
+
+impl RoughDielectric {
+    pub fn new(index: f32, roughness: f32) -> Self {
+        Self::textured(index, Arc::new(SolidColor::new(Color::new(roughness, roughness, roughness))))
+    }
+
+    pub fn textured(index: f32, roughness: Arc<dyn Texture + Send + Sync>) -> Self {
+        Self {index, roughness, absorption: Color::new(0.0, 0.0, 0.0)}
+    }
+
+    fn distribution(&self, rec: &HitRecord) -> TrowbridgeReitz {
+        distribution(self.roughness.value(rec.u, rec.v, &rec.p).x)
+    }
+
+    fn eta(&self, rec: &HitRecord) -> f32 {
+        relative_index(self.index, rec)
+    }
+}
+
+/// Isotropic GGX for a perceptual roughness in [0, 1].
+pub(crate) fn distribution(roughness: f32) -> TrowbridgeReitz {
+    let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+    TrowbridgeReitz::new(alpha, alpha)
+}
+
+/// Index of the side `wi` refracts into over the side of `wo`, for glass of
+/// index `index` hit at `rec`.
+pub(crate) fn relative_index(index: f32, rec: &HitRecord) -> f32 {
+    if rec.front { index } else { 1.0 / index }
+}
+
+/// Microfacet normal shared by `wo` and `wi`, None for degenerate pairs and
+/// normals facing away from either direction.
+fn half_vector(wo: &Vec3, wi: &Vec3, eta: f32) -> Option<Vec3> {
+    if wo.z == 0.0 || wi.z == 0.0 {
+        return None;
+    }
+    let etap = if wi.z > 0.0 { 1.0 } else { eta };
+    let wm = *wi * etap + *wo;
+    if wm.near_zero() {
+        return None;
+    }
+    let wm = wm.normalize();
+    let wm = if wm.z < 0.0 { -wm } else { wm };
+    if wm.dot(wi) * wi.z < 0.0 || wm.dot(wo) * wo.z < 0.0 {
+        return None;
+    }
+    Some(wm)
+}
+
+/// BSDF and density of rough glass, for local directions with `wo` above
+/// the surface and `eta` the index below over the index above.
+pub fn rough_eval_pdf(wo: &Vec3, wi: &Vec3, eta: f32, distrib: &TrowbridgeReitz) -> (f32, f32) {
+    let wm = match half_vector(wo, wi, eta) {
+        Some(wm) => wm,
+        None => return (0.0, 0.0),
+    };
+    let fresnel = fresnel_dielectric(wo.dot(&wm), eta);
+    let d = distrib.d(&wm) * distrib.g(wo, wi);
+    if wi.z > 0.0 {
+        let f = d * fresnel / (4.0 * wi.z * wo.z);
+        let pdf = distrib.pdf(wo, &wm) / (4.0 * wo.dot(&wm).abs()) * fresnel;
+        (f, pdf)
+    } else {
+        let denom = wi.dot(&wm) + wo.dot(&wm) / eta;
+        let denom = denom * denom;
+        let f = d * (1.0 - fresnel) * (wi.dot(&wm) * wo.dot(&wm) / (denom * wi.z * wo.z)).abs();
+        let pdf = distrib.pdf(wo, &wm) * wi.dot(&wm).abs() / denom * (1.0 - fresnel);
+        (f, pdf)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color {
+        let frame = rec.shading_frame();
+        let (wo, wi) = (frame.to_local(*wo), frame.to_local(*wi));
+        let distrib = self.distribution(rec);
+        if wo.z <= 0.0 || distrib.is_smooth() {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let (f, _) = rough_eval_pdf(&wo, &wi, self.eta(rec), &distrib);
+        Color::new(f, f, f)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32 {
+        let frame = rec.shading_frame();
+        let (wo, wi) = (frame.to_local(*wo), frame.to_local(*wi));
+        let distrib = self.distribution(rec);
+        if wo.z <= 0.0 || distrib.is_smooth() {
+            return 0.0;
+        }
+        rough_eval_pdf(&wo, &wi, self.eta(rec), &distrib).1
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, uc: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        let frame = rec.shading_frame();
+        let wo_local = frame.to_local(*wo);
+        if wo_local.z <= 0.0 {
+            return None;
+        }
+        let eta = self.eta(rec);
+        let distrib = self.distribution(rec);
+
+        // Reflect or refract about a microfacet normal, picked by Fresnel
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let wm = if distrib.is_smooth() { normal } else { distrib.sample_wm(&wo_local, u) };
+        let fresnel = fresnel_dielectric(wo_local.dot(&wm), eta);
+        let wi = if uc < fresnel {
+            let wi = wm * (2.0 * wo_local.dot(&wm)) - wo_local;
+            if wi.z <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = refract(&wo_local, &wm, eta)?;
+            if wi.z >= 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        if distrib.is_smooth() {
+            let pdf = if wi.z > 0.0 { fresnel } else { 1.0 - fresnel };
+            let f = pdf / wi.z.abs();
+            return Some(BsdfSample {wi: frame.to_world(wi), f: Color::new(f, f, f), pdf, specular: true});
+        }
+        let (f, pdf) = rough_eval_pdf(&wo_local, &wi, eta, &distrib);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {wi: frame.to_world(wi), f: Color::new(f, f, f), pdf, specular: false})
+    }
+
+    fn transmittance(&self, distance: f32) -> Color {
+        beer_lambert(&self.absorption, distance)
+    }
+
+    fn has_interior(&self) -> bool {
+        true
+    }
+}
+
+/// Glass pane with both faces in one surface, such as a window. Light goes
+/// straight through without refracting, and the reflections bouncing between
+/// the two faces are summed up.
+pub struct ThinDielectric {
+    pub index: f32,
+    /// Colour of the light let through
+    pub tint: Color,
+}
+
+impl ThinDielectric {
+    pub fn new(index: f32, tint: Color) -> Self {
+        Self {index, tint}
+    }
+}
+
+impl Material for ThinDielectric {
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> f32 {
+        0.0
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, uc: f32, _u: [f32; 2]) -> Option<BsdfSample> {
+        let cos_theta = wo.dot(&rec.n);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let mut r = fresnel_dielectric(cos_theta, self.index);
+        if r < 1.0 {
+            // Geometric series of the internal reflections
+            let t = 1.0 - r;
+            r += t * t * r / (1.0 - r * r);
+        }
+
+        if uc < r {
+            let wi = rec.n * (2.0 * cos_theta) - *wo;
+            Some(BsdfSample {wi, f: Color::new(1.0, 1.0, 1.0) * (r / cos_theta), pdf: r, specular: true})
+        } else {
+            let t = 1.0 - r;
+            Some(BsdfSample {wi: -*wo, f: self.tint * (t / cos_theta), pdf: t, specular: true})
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::check_bsdf;
+
+    #[test]
+    fn rough_glass_is_consistent() {
+        check_bsdf(Arc::new(RoughDielectric::new(1.5, 0.3)));
+    }
+
+    #[test]
+    fn very_rough_water_is_consistent() {
+        check_bsdf(Arc::new(RoughDielectric::new(1.33, 0.8)));
+    }
+}
@@ -0,0 +1,203 @@
+//! Reader for Radiance RGBE (`.hdr`) images.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::framebuffer::Framebuffer;
+use crate::vector::Color;
+
+pub fn load_hdr(path: &Path) -> Result<Framebuffer<Color>, Box<dyn Error>> {
+    let data = fs::read(path)
+        .map_err(|e| format!("Unable to read image '{}': {}", path.display(), e))?;
+    read_hdr(&data).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+pub fn read_hdr(data: &[u8]) -> Result<Framebuffer<Color>, String> {
+    let mut pos = 0;
+    let mut next_line = || -> Result<&[u8], String> {
+        let len = data[pos..].iter()
+            .position(|&b| b == b'\n')
+            .ok_or("unexpected end of header")?;
+        let line = &data[pos..pos + len];
+        pos += len + 1;
+        Ok(line)
+    };
+
+    let magic = next_line()?;
+    if !magic.starts_with(b"#?") {
+        return Err("not a Radiance HDR file".into());
+    }
+    // Variables until an empty line, then the resolution
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix(b"FORMAT=") {
+            if format != b"32-bit_rle_rgbe" {
+                return Err(format!("unsupported format '{}'", String::from_utf8_lossy(format)));
+            }
+        }
+    }
+    let resolution = String::from_utf8_lossy(next_line()?).into_owned();
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>().map_err(|_| "invalid image height")?,
+            w.parse::<usize>().map_err(|_| "invalid image width")?,
+        ),
+        _ => return Err(format!("unsupported orientation '{}'", resolution)),
+    };
+
+    // Every scanline takes some bytes, so a header claiming more pixels than
+    // the data can hold is rejected before allocating them
+    let mut rest = &data[pos..];
+    let min_scanline = if (8..0x8000).contains(&width) {
+        // Header and runs of at most 127 values for each channel
+        Some(4 + 8 * width.div_ceil(127))
+    } else {
+        width.checked_mul(4)
+    };
+    let min_size = min_scanline.and_then(|n| n.checked_mul(height));
+    if min_size.is_none_or(|n| n > rest.len()) {
+        return Err(format!("{}x{} pixels do not fit in the file", width, height));
+    }
+
+    let mut image = Framebuffer::<Color>::new(width, height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for row in image.pixels.chunks_mut(width.max(1)) {
+        rest = read_scanline(rest, &mut scanline)?;
+        for (pixel, rgbe) in row.iter_mut().zip(scanline.iter()) {
+            *pixel = rgbe_to_color(*rgbe);
+        }
+    }
+    Ok(image)
+}
+
+/// Reads one scanline, either flat or with the per-channel run length
+/// encoding, and returns the remaining data.
+fn read_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8], String> {
+    let width = scanline.len();
+    let truncated = || "truncated pixel data".to_string();
+    let is_rle = (8..0x8000).contains(&width)
+        && data.len() >= 4
+        && data[0] == 2
+        && data[1] == 2
+        && data[2] & 0x80 == 0;
+    if !is_rle {
+        let bytes = data.get(..4 * width).ok_or_else(truncated)?;
+        for (pixel, rgbe) in scanline.iter_mut().zip(bytes.chunks_exact(4)) {
+            pixel.copy_from_slice(rgbe);
+        }
+        return Ok(&data[4 * width..]);
+    }
+
+    if ((data[2] as usize) << 8 | data[3] as usize) != width {
+        return Err("scanline width mismatch".into());
+    }
+    let mut pos = 4;
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(pos).ok_or_else(truncated)? as usize;
+            pos += 1;
+            if count > 128 {
+                // Run of a single value
+                let count = count - 128;
+                let value = *data.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                if x + count > width {
+                    return Err("run exceeds scanline".into());
+                }
+                for pixel in scanline[x..x + count].iter_mut() {
+                    pixel[channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err("invalid run length".into());
+                }
+                let values = data.get(pos..pos + count).ok_or_else(truncated)?;
+                pos += count;
+                for (pixel, &value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(&data[pos..])
+}
+
+fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let scale = f32::powi(2.0, rgbe[3] as i32 - (128 + 8));
+    Color::new(
+        (rgbe[0] as f32 + 0.5) * scale,
+        (rgbe[1] as f32 + 0.5) * scale,
+        (rgbe[2] as f32 + 0.5) * scale,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n";
+
+    /// Pixel `x` of the test image, the same on both rows.
+    fn rgbe(x: usize) -> [u8; 4] {
+        [10 * x as u8 + 5, 64, 200, 129]
+    }
+
+    /// Both rows of the test image, the first one flat and the second one run
+    /// length encoded, with literal red values and runs for the rest.
+    fn encoded() -> Vec<u8> {
+        let mut data = HEADER.to_vec();
+        for x in 0..8 {
+            data.extend_from_slice(&rgbe(x));
+        }
+        data.extend_from_slice(&[2, 2, 0, 8]);
+        data.push(8);
+        data.extend((0..8).map(|x| rgbe(x)[0]));
+        data.extend_from_slice(&[128 + 8, 64, 128 + 8, 200, 128 + 8, 129]);
+        data
+    }
+
+    #[test]
+    fn flat_and_run_length_encoded_scanlines() {
+        let image = read_hdr(&encoded()).unwrap();
+        assert_eq!((image.width, image.height), (8, 2));
+        for x in 0..8 {
+            let expected = rgbe_to_color(rgbe(x));
+            for y in 0..2 {
+                let c = image.pixels[y * 8 + x];
+                assert_eq!([c.x, c.y, c.z], [expected.x, expected.y, expected.z]);
+            }
+        }
+        // Mantissas are in units of 2^(exponent - 136), rounded to the centre
+        let c = rgbe_to_color([128, 64, 32, 129]);
+        assert_eq!([c.x, c.y, c.z], [128.5 / 128.0, 64.5 / 128.0, 32.5 / 128.0]);
+        let black = rgbe_to_color([12, 34, 56, 0]);
+        assert_eq!([black.x, black.y, black.z], [0.0; 3]);
+    }
+
+    #[test]
+    fn truncated_data_is_an_error() {
+        let data = encoded();
+        for len in [HEADER.len() - 1, HEADER.len() + 10, data.len() - 1] {
+            assert!(read_hdr(&data[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn oversized_header_is_an_error() {
+        for resolution in ["-Y 100000000 +X 100000000", "-Y 1 +X 18446744073709551615"] {
+            let mut data = format!("#?RADIANCE\n\n{}\n", resolution).into_bytes();
+            data.extend_from_slice(&[0; 64]);
+            assert!(read_hdr(&data).is_err(), "{}", resolution);
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::exr::{self, ExrChannel, ExrPixelType, ExrCompression};
+use crate::framebuffer::{Framebuffer, RenderLayers};
+use crate::hdr;
+use crate::tonemap::{self, ToneMapper};
+use crate::vector::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Pfm,
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let ext = path.extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("ppm") => Ok(ImageFormat::Ppm),
+            Some("png") => Ok(ImageFormat::Png),
+            Some("pfm") => Ok(ImageFormat::Pfm),
+            Some("exr") => Ok(ImageFormat::Exr),
+            _ => Err(format!(
+                "Unsupported output format for '{}', use .ppm, .png, .pfm or .exr",
+                path.display(),
+            ).into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SaveOptions {
+    pub tone_mapper: ToneMapper,
+    pub exr_pixel_type: ExrPixelType,
+    pub exr_compression: ExrCompression,
+}
+
+/// Writes `layers` to `path` in the format given by its extension. Float
+/// formats store linear radiance as is, 8-bit ones go through the tone mapper.
+/// Only EXR keeps the extra layers.
+pub fn save(
+    path: &Path,
+    layers: &RenderLayers,
+    options: &SaveOptions,
+) -> Result<(), Box<dyn Error>> {
+    let image = &layers.beauty;
+    let format = ImageFormat::from_path(path)?;
+    let fh = File::create(path)
+        .map_err(|e| format!("Unable to create the file '{}': {}", path.display(), e))?;
+    let mut fh = BufWriter::new(fh);
+    match format {
+        ImageFormat::Ppm => write_ppm(&mut fh, &options.tone_mapper.apply(image))?,
+        ImageFormat::Png => write_png(&mut fh, &options.tone_mapper.apply(image))?,
+        ImageFormat::Pfm => write_pfm(&mut fh, image)?,
+        ImageFormat::Exr => write_layers_exr(&mut fh, layers, options)?,
+    }
+    fh.flush()?;
+    Ok(())
+}
+
+/// Rejects images without pixels, which the formats cannot store.
+fn check_not_empty<T>(image: &Framebuffer<T>) -> io::Result<()> {
+    if image.width == 0 || image.height == 0 {
+        let message = format!("cannot write an empty {}x{} image", image.width, image.height);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    Ok(())
+}
+
+/// Binary (P6) PPM.
+pub fn write_ppm<W: Write>(w: &mut W, image: &Framebuffer<[u8; 3]>) -> io::Result<()> {
+    check_not_empty(image)?;
+    write!(w, "P6\n{} {}\n255\n", image.width, image.height)?;
+    for pixel in image.pixels.iter() {
+        w.write_all(pixel)?;
+    }
+    Ok(())
+}
+
+/// 8-bit RGB PNG with zlib-compressed scanlines and no filtering.
+pub fn write_png<W: Write>(w: &mut W, image: &Framebuffer<[u8; 3]>) -> io::Result<()> {
+    check_not_empty(image)?;
+    w.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    // Header: size, 8 bits per channel, truecolour, no interlacing
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(image.width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(image.height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_png_chunk(w, b"IHDR", &ihdr)?;
+
+    // Every scanline starts with its filter type
+    let mut raw = Vec::with_capacity(image.height * (1 + 3 * image.width));
+    for row in image.pixels.chunks(image.width) {
+        raw.push(0);
+        for pixel in row {
+            raw.extend_from_slice(pixel);
+        }
+    }
+    let data = miniz_oxide::deflate::compress_to_vec_zlib(&raw, 6);
+    write_png_chunk(w, b"IDAT", &data)?;
+
+    write_png_chunk(w, b"IEND", &[])
+}
+
+fn write_png_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = crc32(crc32(0xffff_ffff, kind), data) ^ 0xffff_ffff;
+    w.write_all(&crc.to_be_bytes())
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    crc
+}
+
+/// Little-endian colour PFM. Rows are stored bottom to top.
+pub fn write_pfm<W: Write>(w: &mut W, image: &Framebuffer<Color>) -> io::Result<()> {
+    check_not_empty(image)?;
+    write!(w, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
+    for row in image.pixels.chunks(image.width).rev() {
+        for pixel in row {
+            w.write_all(&pixel.x.to_le_bytes())?;
+            w.write_all(&pixel.y.to_le_bytes())?;
+            w.write_all(&pixel.z.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// EXR with the beauty in R, G, B, depth in Z and normals in N.X, N.Y, N.Z.
+pub fn write_layers_exr<W: Write>(
+    w: &mut W,
+    layers: &RenderLayers,
+    options: &SaveOptions,
+) -> io::Result<()> {
+    let split = |pixels: &[Color]| -> [Vec<f32>; 3] {
+        [
+            pixels.iter().map(|p| p.x).collect(),
+            pixels.iter().map(|p| p.y).collect(),
+            pixels.iter().map(|p| p.z).collect(),
+        ]
+    };
+
+    let mut planes: Vec<(String, Vec<f32>)> = vec![];
+    let [r, g, b] = split(&layers.beauty.pixels);
+    planes.extend([("R".into(), r), ("G".into(), g), ("B".into(), b)]);
+    if let Some(depth) = &layers.depth {
+        planes.push(("Z".into(), depth.pixels.clone()));
+    }
+    if let Some(normal) = &layers.normal {
+        let [x, y, z] = split(&normal.pixels);
+        planes.extend([("N.X".into(), x), ("N.Y".into(), y), ("N.Z".into(), z)]);
+    }
+
+    let channels: Vec<ExrChannel> = planes.iter()
+        .map(|(name, data)| ExrChannel {
+            name: name.clone(),
+            pixel_type: options.exr_pixel_type,
+            data,
+        })
+        .collect();
+    exr::write_exr(
+        w,
+        layers.beauty.width,
+        layers.beauty.height,
+        &channels,
+        options.exr_compression,
+    )
+}
+
+/// Reads a PNG, binary PPM or Radiance HDR image into linear colours. 8 and
+/// 16-bit images are decoded from sRGB if `srgb` is set, otherwise their
+/// values are only brought into [0, 1], as needed for normal or height maps.
+pub fn load_image(path: &Path, srgb: bool) -> Result<Framebuffer<Color>, Box<dyn Error>> {
+    let ext = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    if ext.as_deref() == Some("hdr") {
+        return hdr::load_hdr(path);
+    }
+    let data = fs::read(path)
+        .map_err(|e| format!("Unable to read image '{}': {}", path.display(), e))?;
+    let image = match ext.as_deref() {
+        Some("png") => read_png(&data, srgb),
+        Some("ppm") => read_ppm(&data, srgb),
+        _ => Err("unsupported image format, use .png, .ppm or .hdr".to_string()),
+    };
+    image.map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Non-interlaced PNG of any colour type with 8 or 16 bits per channel, or
+/// palette images with 8 bits per index. Alpha is ignored.
+pub fn read_png(data: &[u8], srgb: bool) -> Result<Framebuffer<Color>, String> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err("not a PNG file".into());
+    }
+
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut compressed = vec![];
+    let mut ended = false;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let chunk = data.get(pos + 8..).and_then(|rest| rest.get(..len)).ok_or("truncated chunk")?;
+        pos += 12 + len;
+        match kind {
+            b"IHDR" if chunk.len() == 13 => header = Some(chunk),
+            b"PLTE" => palette = chunk,
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => {
+                ended = true;
+                break;
+            },
+            _ => {},
+        }
+    }
+    if !ended {
+        return Err("truncated file, no IEND chunk".into());
+    }
+    let header = header.ok_or("missing header")?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if interlace != 0 {
+        return Err("interlaced images are not supported".into());
+    }
+    let channels = match (color_type, depth) {
+        (0, 8 | 16) => 1,
+        (2, 8 | 16) => 3,
+        (3, 8) => 1,
+        (4, 8 | 16) => 2,
+        (6, 8 | 16) => 4,
+        _ => return Err(format!("unsupported colour type {} with depth {}", color_type, depth)),
+    };
+
+    let raw = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed)
+        .map_err(|e| format!("corrupt image data: {:?}", e.status))?;
+    let bpp = channels * depth as usize / 8;
+    let stride = width.checked_mul(bpp).ok_or("image too large")?;
+    let size = (stride + 1).checked_mul(height).ok_or("image too large")?;
+    if raw.len() < size {
+        return Err("truncated image data".into());
+    }
+
+    // Undo the per-scanline filters
+    let mut pixels = vec![0u8; height * stride];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = pixels.split_at_mut(y * stride);
+        let prev = if y > 0 { &done[(y - 1) * stride..] } else { &[][..] };
+        let current = &mut rest[..stride];
+        for x in 0..stride {
+            let a = if x >= bpp { current[x - bpp] as i16 } else { 0 };
+            let b = if y > 0 { prev[x] as i16 } else { 0 };
+            let c = if x >= bpp && y > 0 { prev[x - bpp] as i16 } else { 0 };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => (a + b) / 2,
+                4 => {
+                    // Paeth
+                    let p = a + b - c;
+                    let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+                    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+                },
+                _ => return Err(format!("invalid filter type {}", filter)),
+            };
+            current[x] = line[x].wrapping_add(predictor as u8);
+        }
+    }
+
+    let sample = |i: usize| -> f32 {
+        if depth == 16 {
+            u16::from_be_bytes([pixels[2 * i], pixels[2 * i + 1]]) as f32 / 65535.0
+        } else {
+            pixels[i] as f32 / 255.0
+        }
+    };
+    let mut image = Framebuffer::<Color>::new(width, height);
+    for (i, pixel) in image.pixels.iter_mut().enumerate() {
+        let rgb = match color_type {
+            0 | 4 => {
+                let v = sample(i * channels);
+                [v, v, v]
+            },
+            3 => {
+                let index = pixels[i] as usize;
+                let entry = palette.get(3 * index..3 * index + 3).ok_or("palette index out of range")?;
+                [entry[0] as f32 / 255.0, entry[1] as f32 / 255.0, entry[2] as f32 / 255.0]
+            },
+            _ => [sample(i * channels), sample(i * channels + 1), sample(i * channels + 2)],
+        };
+        let [r, g, b] = if srgb { rgb.map(tonemap::srgb_to_linear) } else { rgb };
+        *pixel = Color::new(r, g, b);
+    }
+    Ok(image)
+}
+
+/// Binary (P6) PPM with a maximum value up to 255.
+pub fn read_ppm(data: &[u8], srgb: bool) -> Result<Framebuffer<Color>, String> {
+    // Magic number, width, height and maximum value, separated by white space
+    // and possibly comments
+    let mut pos = 0;
+    let mut fields = vec![];
+    while fields.len() < 4 {
+        while pos < data.len() && (data[pos].is_ascii_whitespace() || data[pos] == b'#') {
+            if data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("truncated header".into());
+        }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    // A single white space character ends the header
+    pos += 1;
+
+    if fields[0] != "P6" {
+        return Err("only binary (P6) PPM files are supported".into());
+    }
+    let parse = |s: &str| s.parse::<usize>().map_err(|_| format!("invalid header value '{}'", s));
+    let (width, height, max) = (parse(&fields[1])?, parse(&fields[2])?, parse(&fields[3])?);
+    if max == 0 || max > 255 {
+        return Err(format!("unsupported maximum value {}", max));
+    }
+    let len = width.checked_mul(height)
+        .and_then(|n| n.checked_mul(3))
+        .ok_or("image too large")?;
+    let bytes = data.get(pos..).and_then(|rest| rest.get(..len)).ok_or("truncated image data")?;
+
+    let mut image = Framebuffer::<Color>::new(width, height);
+    for (pixel, rgb) in image.pixels.iter_mut().zip(bytes.chunks_exact(3)) {
+        let [r, g, b] = [rgb[0], rgb[1], rgb[2]].map(|v| {
+            let v = v as f32 / max as f32;
+            if srgb { tonemap::srgb_to_linear(v) } else { v }
+        });
+        *pixel = Color::new(r, g, b);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small image with every byte value showing up somewhere.
+    fn gradient(width: usize, height: usize) -> Framebuffer<[u8; 3]> {
+        let mut image = Framebuffer::new(width, height);
+        for (i, pixel) in image.pixels.iter_mut().enumerate() {
+            *pixel = [(i * 7) as u8, (i * 13 + 50) as u8, (255 - i) as u8];
+        }
+        image
+    }
+
+    fn assert_decodes_to(decoded: &Framebuffer<Color>, image: &Framebuffer<[u8; 3]>) {
+        assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+        for (c, p) in decoded.pixels.iter().zip(image.pixels.iter()) {
+            let bytes = [c.x, c.y, c.z].map(|v| (v * 255.0).round() as u8);
+            assert_eq!(&bytes, p);
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = gradient(17, 9);
+        let mut data = vec![];
+        write_png(&mut data, &image).unwrap();
+        assert_decodes_to(&read_png(&data, false).unwrap(), &image);
+    }
+
+    #[test]
+    fn png_filters() {
+        // Sub then Up filtered scanlines of a 2x2 greyscale image
+        let raw = [1, 10, 5, 2, 3, 4];
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        let ihdr = [0, 0, 0, 2, 0, 0, 0, 2, 8, 0, 0, 0, 0];
+        write_png_chunk(&mut data, b"IHDR", &ihdr).unwrap();
+        write_png_chunk(&mut data, b"IDAT", &miniz_oxide::deflate::compress_to_vec_zlib(&raw, 6)).unwrap();
+        write_png_chunk(&mut data, b"IEND", &[]).unwrap();
+        let image = read_png(&data, false).unwrap();
+        let values: Vec<u8> = image.pixels.iter().map(|c| (c.x * 255.0).round() as u8).collect();
+        assert_eq!(values, [10, 15, 13, 19]);
+    }
+
+    #[test]
+    fn truncated_png_is_an_error() {
+        let mut data = vec![];
+        write_png(&mut data, &gradient(17, 9)).unwrap();
+        for len in [4, 20, data.len() / 2, data.len() - 13] {
+            assert!(read_png(&data[..len], false).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn ppm_round_trip() {
+        let image = gradient(17, 9);
+        let mut data = vec![];
+        write_ppm(&mut data, &image).unwrap();
+        assert_decodes_to(&read_ppm(&data, false).unwrap(), &image);
+    }
+
+    #[test]
+    fn ppm_header_with_comments() {
+        let data = b"P6 # comment\n2 1\n# another\n255\n\x00\x80\xff\xff\x80\x00";
+        let image = read_ppm(data, false).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels[1].x, 1.0);
+    }
+
+    #[test]
+    fn truncated_or_oversized_ppm_is_an_error() {
+        let mut data = vec![];
+        write_ppm(&mut data, &gradient(17, 9)).unwrap();
+        assert!(read_ppm(&data[..data.len() - 1], false).is_err());
+        assert!(read_ppm(b"P6\n17 9\n", false).is_err());
+        let huge = format!("P6\n{} {}\n255\n", usize::MAX, usize::MAX);
+        assert!(read_ppm(huge.as_bytes(), false).is_err());
+    }
+
+    #[test]
+    fn pfm_rows_go_bottom_to_top() {
+        let mut image = Framebuffer::<Color>::new(1, 2);
+        image.pixels[0] = Color::new(1.0, 2.0, 3.0);
+        image.pixels[1] = Color::new(4.0, 5.0, 6.0);
+        let mut data = vec![];
+        write_pfm(&mut data, &image).unwrap();
+        let header = b"PF\n1 2\n-1.0\n";
+        assert!(data.starts_with(header));
+        let values: Vec<f32> = data[header.len()..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, [4.0, 5.0, 6.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn empty_images_are_rejected() {
+        let mut data = vec![];
+        assert!(write_png(&mut data, &Framebuffer::new(0, 4)).is_err());
+        assert!(write_ppm(&mut data, &Framebuffer::new(4, 0)).is_err());
+        assert!(write_pfm(&mut data, &Framebuffer::new(0, 0)).is_err());
+    }
+}
@@ -1,21 +1,57 @@
-mod utilities;
-mod vector;
-mod ray;
-mod objects;
-mod camera;
-mod scenes;
+pub mod utilities;
+pub mod rng;
+pub mod sampling;
+pub mod sampler;
+pub mod vector;
+pub mod ray;
+pub mod aabb;
+pub mod bvh;
+pub mod objects;
+pub mod lights;
+pub mod environment;
+pub mod sky;
+pub mod triangle;
+pub mod obj;
+pub mod camera;
+pub mod framebuffer;
+pub mod scenes;
+pub mod scene_file;
+pub mod image_io;
+pub mod exr;
+pub mod hdr;
+pub mod tonemap;
+pub mod texture;
+pub mod bump;
+pub mod microfacet;
+pub mod conductor;
+pub mod glass;
+pub mod coated;
+pub mod principled;
+pub mod mix;
+pub mod noise;
+#[cfg(test)]
+mod test_util;
 
 use std::error::Error;
+use std::path::PathBuf;
 use rand::{Rng, thread_rng};
-use std::sync::{Arc, Mutex};
+use rng::SampleRng;
+use sampler::SamplerKind;
 use indicatif::ParallelProgressIterator;
 use rayon::{self, iter::*};
 use clap::Parser;
 
 use camera::Camera;
-use ray::{ray_color, postprocess_color};
-use vector::{Point3, Color};
+use ray::ray_color;
+use vector::{Point3, Vec3, Color};
 use objects::Scene;
+use environment::{Background, EnvironmentMap};
+use sky::PhysicalSky;
+use framebuffer::{Framebuffer, Aov, RenderLayers};
+use image_io::SaveOptions;
+use exr::{ExrPixelType, ExrCompression};
+use scene_file::CameraSettings;
+use tonemap::{ToneMapper, ToneMapOperator, TransferCurve};
 
 #[derive(Parser)]
 pub struct Config {
@@ -41,98 +77,190 @@ pub struct Config {
     pub num_threads: usize,
     #[clap(long, default_value_t = 1000)]
     pub batch_size: usize,
+    /// Seed for every random draw, a random one is picked if not given
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Pattern used for pixel, lens and bounce samples
+    #[clap(long, value_enum, default_value_t = SamplerKind::Independent)]
+    pub sampler: SamplerKind,
+    /// TOML scene description to render instead of the built-in random scene
+    #[clap(long)]
+    pub scene: Option<PathBuf>,
+    /// Equirectangular Radiance HDR image lighting the scene, replaces the
+    /// background of the scene
+    #[clap(long)]
+    pub environment: Option<PathBuf>,
+    /// Rotation of the environment map around the vertical axis, in degrees
+    #[clap(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub environment_rotation: f32,
+    /// Radiance scale of the environment map
+    #[clap(long, default_value_t = 1.0)]
+    pub environment_intensity: f32,
+    /// Light the scene with a physical sky and sun, replaces the background
+    /// of the scene
+    #[clap(long, conflicts_with = "environment")]
+    pub physical_sky: bool,
+    /// Angle of the sun above the horizon, in degrees
+    #[clap(long, default_value_t = 45.0, allow_negative_numbers = true)]
+    pub sun_elevation: f32,
+    /// Angle of the sun clockwise from -z seen from above, in degrees
+    #[clap(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub sun_azimuth: f32,
+    /// Haziness of the physical sky, from 2 (clear) to 10
+    #[clap(long, default_value_t = 3.0)]
+    pub turbidity: f32,
+    /// Output image, the format is chosen by extension (.ppm, .png or .pfm)
+    #[clap(long, default_value = "test.ppm")]
+    pub output: PathBuf,
+    /// Operator used to bring radiance into the displayable range
+    #[clap(long, value_enum, default_value_t = ToneMapOperator::Clamp)]
+    pub tonemap: ToneMapOperator,
+    /// Exposure compensation in stops, applied before tone mapping
+    #[clap(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f32,
+    /// Transfer curve used to encode 8-bit outputs
+    #[clap(long, value_enum, default_value_t = TransferCurve::Srgb)]
+    pub transfer: TransferCurve,
+    /// Extra layers to render, only stored in EXR outputs
+    #[clap(long, value_enum)]
+    pub aov: Vec<Aov>,
+    /// Channel precision of EXR outputs
+    #[clap(long, value_enum, default_value_t = ExrPixelType::Float)]
+    pub exr_pixel_type: ExrPixelType,
+    /// Compression of EXR outputs
+    #[clap(long, value_enum, default_value_t = ExrCompression::Zip)]
+    pub exr_compression: ExrCompression,
 }
 
-pub fn run(conf: &Config) -> Result<Vec<[i32; 3]>, Box<dyn Error>> {
+impl Config {
+    pub fn camera_settings(&self) -> CameraSettings {
+        CameraSettings {
+            lookfrom: self.lookfrom,
+            lookat: self.lookat,
+            vup: self.vup,
+            vfov: 30.0,
+            aperture: self.aperture,
+            dist_to_focus: self.dist_to_focus,
+        }
+    }
+
+    pub fn tone_mapper(&self) -> ToneMapper {
+        ToneMapper::new(self.tonemap, self.exposure, self.transfer)
+    }
+
+    pub fn save_options(&self) -> SaveOptions {
+        SaveOptions {
+            tone_mapper: self.tone_mapper(),
+            exr_pixel_type: self.exr_pixel_type,
+            exr_compression: self.exr_compression,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PixelSample {
+    color: Color,
+    depth: f32,
+    normal: Vec3,
+}
+
+/// Renders the scene and returns the linear radiance of every pixel.
+pub fn run(conf: &Config) -> Result<Framebuffer<Color>, Box<dyn Error>> {
+    run_layers(conf).map(|layers| layers.beauty)
+}
 
-    let image = Arc::new(Mutex::new(vec![[0, 0, 0]; conf.image_height * conf.image_width]));
+/// Renders the scene along with the extra layers requested in `conf.aov`.
+pub fn run_layers(conf: &Config) -> Result<RenderLayers, Box<dyn Error>> {
+
+    let mut image = Framebuffer::<PixelSample>::new(conf.image_width, conf.image_height);
+
+    // World, camera fields in a scene file take precedence over the CLI ones
+    let seed = conf.seed.unwrap_or_else(|| thread_rng().gen());
+
+    let (mut world, settings, warnings) = match &conf.scene {
+        Some(path) => {
+            let file = scene_file::load(path)?;
+            (file.scene, file.camera.apply(conf.camera_settings()), file.warnings)
+        },
+        None => (scenes::random_scene(&mut SampleRng::seeded(seed)), conf.camera_settings(), vec![]),
+    };
+    if let Some(path) = &conf.environment {
+        let map = EnvironmentMap::load(path, conf.environment_rotation, conf.environment_intensity)?;
+        world.set_background(Background::Map(map));
+    }
+    if conf.physical_sky {
+        world.set_physical_sky(PhysicalSky::new(conf.sun_elevation, conf.sun_azimuth, conf.turbidity, 1.0));
+    }
+    world.build_bvh();
 
     let cam = Camera::new(
-        conf.lookfrom,
-        conf.lookat,
-        conf.vup,
-        30.0,
+        settings.lookfrom,
+        settings.lookat,
+        settings.vup,
+        settings.vfov,
         conf.image_width as f32 / conf.image_height as f32,
-        conf.aperture,
-        conf.dist_to_focus,
+        settings.aperture,
+        settings.dist_to_focus,
     );
 
-    // World
-    // let world = scenes::test_scene();
-    let world = scenes::random_scene();
-
     // Render
     let thread_pool = rayon::ThreadPoolBuilder::new()
         .num_threads(conf.num_threads)
-        .build()
-        .unwrap();
+        .build()?;
 
-    let full_loops = conf.image_width * conf.image_height / conf.batch_size;
-    let final_loop = conf.image_width * conf.image_height % conf.batch_size;
+    let num_batches = (conf.image_width * conf.image_height).div_ceil(conf.batch_size);
 
     thread_pool.install(|| {
-        (0..full_loops).into_par_iter().progress_count(full_loops as u64).for_each(|i| {
-            let start = i * conf.batch_size;
-            let end = start + conf.batch_size;
-            let image_ref = Arc::clone(&image);
-            render_task(
-                image_ref,
-                start,
-                end,
-                conf.image_width,
-                conf.image_height,
-                conf.samples_per_pixel,
-                conf.max_depth,
-                &cam,
-                &world,
-            )
-        });
+        image
+            .par_batches_mut(conf.batch_size)
+            .progress_count(num_batches as u64)
+            .for_each(|(start, pixels)| render_task(pixels, start, seed, conf, &cam, &world));
     });
 
-    if final_loop > 0 {
-        let start = full_loops * conf.batch_size;
-        let end = conf.image_width * conf.image_height;
-        let image_ref = image.clone();
-        render_task(
-            image_ref,
-            start,
-            end,
-            conf.image_width,
-            conf.image_height,
-            conf.samples_per_pixel,
-            conf.max_depth,
-            &cam,
-            &world,
-        );
-    }
-
-    let image = image.lock().unwrap().to_vec();
-    Ok(image)
+    // Split the samples into layers
+    Ok(RenderLayers {
+        beauty: image.map(|p| p.color),
+        depth: conf.aov.contains(&Aov::Depth).then(|| image.map(|p| p.depth)),
+        normal: conf.aov.contains(&Aov::Normal).then(|| image.map(|p| p.normal)),
+        seed,
+        warnings,
+    })
 }
 
 fn render_task(
-    image: Arc<Mutex<Vec<[i32; 3]>>>,
+    pixels: &mut [PixelSample],
     start: usize,
-    end: usize,
-    image_width: usize,
-    image_height: usize,
-    samples_per_pixel: usize,
-    max_depth: usize,
+    seed: u64,
+    conf: &Config,
     cam: &Camera,
     world: &Scene,
 ) {
-    for ilocal in start..end {
-        let i = ilocal % image_width;
-        let j = image_height - 1 - (ilocal / image_width);
+    let mut sampler = conf.sampler.create(conf.samples_per_pixel, seed);
+    for (ilocal, pixel) in (start..).zip(pixels.iter_mut()) {
+        let i = ilocal % conf.image_width;
+        let j = conf.image_height - 1 - (ilocal / conf.image_width);
         let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-        for _ in 0..samples_per_pixel {
-            let u = (i as f32 + thread_rng().gen::<f32>()) / (image_width - 1) as f32;
-            let v = (j as f32 + thread_rng().gen::<f32>()) / (image_height - 1) as f32;
-            let r = cam.get_ray(u, v);
-            pixel_color += &ray_color(r, &world, max_depth);
+        for s in 0..conf.samples_per_pixel {
+            sampler.start_pixel_sample(ilocal as u64, s as u64);
+            let [du, dv] = sampler.get_2d();
+            let u = (i as f32 + du) / (conf.image_width - 1) as f32;
+            let v = (j as f32 + dv) / (conf.image_height - 1) as f32;
+            let r = cam.get_ray(u, v, sampler.get_2d());
+
+            // Extra layers come from the first hit of the first sample
+            if s == 0 && !conf.aov.is_empty() {
+                match world.hit(&r, 0.001, 100.0) {
+                    Some(mut rec) => {
+                        rec.perturb_normal();
+                        pixel.depth = rec.t * r.direction.length();
+                        pixel.normal = rec.n;
+                    },
+                    None => pixel.depth = f32::INFINITY,
+                }
+            }
+
+            pixel_color += &ray_color(r, world, conf.max_depth, sampler.as_mut());
         }
-        let mut local_image = image.lock().unwrap();
-        local_image[ilocal] = postprocess_color(pixel_color, samples_per_pixel);
+        pixel.color = pixel_color / conf.samples_per_pixel as f32;
     }
 }
-
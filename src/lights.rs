@@ -0,0 +1,135 @@
+use crate::utilities::deg2rad;
+use crate::vector::{Point3, Vec3, Color};
+
+/// Light arriving at a point from a light source.
+pub struct LightSample {
+    /// Unit direction towards the light
+    pub wi: Vec3,
+    /// Irradiance from the light on a surface facing it, already including
+    /// the falloff
+    pub li: Color,
+    /// Distance to the light along `wi`, infinite for directional lights
+    pub distance: f32,
+}
+
+/// Lights described by a delta distribution, which cannot be hit by rays and
+/// are only reached through shadow rays.
+pub trait Light {
+    /// Light arriving at `p`, `None` if it does not reach it.
+    fn sample_li(&self, p: &Point3) -> Option<LightSample>;
+}
+
+/// Isotropic point light with inverse-square falloff.
+pub struct PointLight {
+    pub position: Point3,
+    pub intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self {position, intensity}
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi: to_light.normalize(),
+            li: self.intensity / distance_squared,
+            distance: distance_squared.sqrt(),
+        })
+    }
+}
+
+/// Point light restricted to a cone, fading smoothly between the falloff
+/// start angle and the cone angle.
+pub struct SpotLight {
+    pub position: Point3,
+    /// Unit axis of the cone, pointing away from the light
+    pub direction: Vec3,
+    pub intensity: Color,
+    cos_cone: f32,
+    cos_falloff_start: f32,
+}
+
+impl SpotLight {
+    /// Angles are in degrees, measured from the axis.
+    pub fn new(
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        cone_angle: f32,
+        falloff_start: f32,
+    ) -> Self {
+        let falloff_start = falloff_start.min(cone_angle);
+        Self {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_cone: deg2rad(cone_angle).cos(),
+            cos_falloff_start: deg2rad(falloff_start).cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f32) -> f32 {
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_cone {
+            return 0.0;
+        }
+        // Smoothstep between the edge of the cone and the falloff start
+        let t = (cos_theta - self.cos_cone) / (self.cos_falloff_start - self.cos_cone);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let wi = to_light.normalize();
+        let falloff = self.falloff(-wi.dot(&self.direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            li: self.intensity * (falloff / distance_squared),
+            distance: distance_squared.sqrt(),
+        })
+    }
+}
+
+/// Light from infinitely far away arriving along a single direction, like
+/// the sun.
+pub struct DirectionalLight {
+    /// Unit direction the light travels in
+    pub direction: Vec3,
+    /// Irradiance on a surface facing the light, in W/m^2
+    pub irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {direction: direction.normalize(), irradiance}
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _p: &Point3) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            li: self.irradiance,
+            distance: f32::INFINITY,
+        })
+    }
+}
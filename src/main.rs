@@ -1,24 +1,38 @@
 use std::error::Error;
-use std::fs::File;
-use std::io::Write;
+use std::process;
 use std::time::Instant;
 
 use clap::Parser;
-use raytracer::{Config, run};
+use raytracer::{Config, run_layers};
+use raytracer::image_io::{self, ImageFormat};
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() {
 
     let conf = Config::parse();
 
+    if let Err(e) = render(&conf) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn render(conf: &Config) -> Result<(), Box<dyn Error>> {
+
+    // Fail before rendering if the output cannot be written
+    ImageFormat::from_path(&conf.output)?;
+
     let now = Instant::now();
-    let image = run(&conf)?;
+    let layers = run_layers(conf)?;
+    for warning in layers.warnings.iter() {
+        eprintln!("Warning: {}", warning);
+    }
     println!("Render time: {} ms.", now.elapsed().as_millis());
-
-    let mut fh = File::create("test.ppm").expect("Unable to create the file 'test.ppm'.");
-    writeln!(fh, "P3\n{} {}\n255", conf.image_width, conf.image_height)?;
-    for pixel in image {
-        writeln!(fh, "{} {} {}", pixel[0], pixel[1], pixel[2])?;
+    if conf.seed.is_none() {
+        // Reported so the render can be reproduced with --seed
+        println!("Seed: {}", layers.seed);
     }
 
+    image_io::save(&conf.output, &layers, &conf.save_options())?;
+
     Ok(())
 }
@@ -0,0 +1,150 @@
+//! Trowbridge-Reitz (GGX) microfacet distribution and Fresnel terms shared by
+//! the rough materials. Directions are in the local shading frame with the
+//! normal along +z.
+
+use crate::sampling;
+use crate::vector::{Color, Vec3};
+use crate::utilities::PI;
+
+/// Below this alpha a surface is treated as a perfect mirror
+const SMOOTH_ALPHA: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TrowbridgeReitz {
+    /// Width of the distribution along the tangent and bitangent
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        Self {alpha_x, alpha_y}
+    }
+
+    /// Perceptually linear roughness in [0, 1] to alpha.
+    pub fn roughness_to_alpha(roughness: f32) -> f32 {
+        let r = roughness.clamp(0.0, 1.0);
+        r * r
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    /// Density of microfacet normals `wm` per projected area.
+    pub fn d(&self, wm: &Vec3) -> f32 {
+        let (ax, ay) = self.alphas();
+        let e = wm.x * wm.x / (ax * ax) + wm.y * wm.y / (ay * ay) + wm.z * wm.z;
+        1.0 / (PI * ax * ay * e * e)
+    }
+
+    /// Smith's auxiliary function, the masked microfacet area per visible area.
+    pub fn lambda(&self, w: &Vec3) -> f32 {
+        if w.z == 0.0 {
+            return f32::INFINITY;
+        }
+        let (ax, ay) = self.alphas();
+        let tan2 = (ax * ax * w.x * w.x + ay * ay * w.y * w.y) / (w.z * w.z);
+        0.5 * (f32::sqrt(1.0 + tan2) - 1.0)
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction visible from both `wo` and `wi`, height-correlated.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals visible from `w`, the pdf of `sample_wm`.
+    pub fn pdf(&self, w: &Vec3, wm: &Vec3) -> f32 {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Heitz's sampling of the visible normals, "Sampling the GGX Distribution
+    /// of Visible Normals" (2018).
+    pub fn sample_wm(&self, w: &Vec3, u: [f32; 2]) -> Vec3 {
+        let (ax, ay) = self.alphas();
+        // Stretch to the hemisphere configuration
+        let mut wh = Vec3::new(ax * w.x, ay * w.y, w.z).normalize();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(&wh).normalize()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // Disk sample squashed onto the visible part of the hemisphere
+        let p = sampling::uniform_disk(u);
+        let h = f32::sqrt(1.0 - p.x * p.x);
+        let s = 0.5 * (1.0 + wh.z);
+        let py = (1.0 - s) * h + s * p.y;
+        let pz = f32::sqrt(f32::max(0.0, 1.0 - p.x * p.x - py * py));
+        let nh = t1 * p.x + t2 * py + wh * pz;
+
+        // Back to the ellipsoid configuration
+        Vec3::new(ax * nh.x, ay * nh.y, nh.z.max(1e-6)).normalize()
+    }
+
+    fn alphas(&self) -> (f32, f32) {
+        (self.alpha_x.max(1e-4), self.alpha_y.max(1e-4))
+    }
+}
+
+/// Fresnel reflectance of a conductor with complex index `eta + i k`, per
+/// channel, for light arriving at `cos_theta` from the normal.
+pub fn fresnel_conductor(cos_theta: f32, eta: &Color, k: &Color) -> Color {
+    let cos_theta = cos_theta.clamp(0.0, 1.0);
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: f32, k: f32| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = f32::sqrt(t0 * t0 + 4.0 * eta * eta * k * k);
+        let a = f32::sqrt(f32::max(0.0, 0.5 * (a2_plus_b2 + t0)));
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos_theta * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+    Color::new(channel(eta.x, k.x), channel(eta.y, k.y), channel(eta.z, k.z))
+}
+
+/// Exact Fresnel reflectance of a dielectric interface for unpolarized light,
+/// `eta` is the index on the far side of the normal over the near side's.
+/// Light arriving from below (`cos_theta` < 0) sees the inverse ratio.
+pub fn fresnel_dielectric(cos_theta: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_theta < 0.0 { (-cos_theta, 1.0 / eta) } else { (cos_theta, eta) };
+    let cos_i = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Refracts `w`, pointing away from the surface on the side of `n`, into
+/// the medium with relative index `eta`. None on total internal reflection.
+pub fn refract(w: &Vec3, n: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = n.dot(w);
+    let sin2_t = f32::max(0.0, 1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    Some(-*w / eta + *n * (cos_i / eta - cos_t))
+}
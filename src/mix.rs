@@ -0,0 +1,250 @@
+//! Materials made out of other materials: blends of two, and cut-outs.
+
+use std::sync::Arc;
+use serde::Deserialize;
+
+use crate::objects::{forward_to_base, BsdfSample, HitRecord, Material};
+use crate::ray::Ray;
+use crate::rng;
+use crate::texture::{SolidColor, Texture};
+use crate::vector::{Color, Vec3};
+
+/// Purposes of the random choices made at a hit.
+const PICK: u64 = 0x7069_636b;
+const OPACITY: u64 = 0x6f70_6163;
+
+/// Random number shared by every query about a hit, for the choice made for
+/// `purpose` by the material seeded with `seed`.
+fn hit_hash(seed: u64, purpose: u64, rec: &HitRecord) -> f32 {
+    rng::hash_unit(seed ^ purpose, &[rec.p.x, rec.p.y, rec.p.z, rec.t])
+}
+
+/// How `MixMaterial` combines its materials.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MixMode {
+    /// Weighted sum of both BSDFs
+    #[default]
+    Blend,
+    /// One of the two picked at random for each hit, which only evaluates one
+    /// material and averages out to the blend over many samples
+    Stochastic,
+}
+
+/// `a` where `amount` is 0 and `b` where it is 1.
+pub struct MixMaterial {
+    pub a: Arc<dyn Material + Send + Sync>,
+    pub b: Arc<dyn Material + Send + Sync>,
+    /// Weight of `b`, read from the first channel
+    pub amount: Arc<dyn Texture + Send + Sync>,
+    pub mode: MixMode,
+    /// Tells apart the stochastic choices of nested materials
+    pub seed: u64,
+}
+
+impl MixMaterial {
+    pub fn new(
+        a: Arc<dyn Material + Send + Sync>,
+        b: Arc<dyn Material + Send + Sync>,
+        amount: f32,
+        mode: MixMode,
+    ) -> Self {
+        Self::textured(a, b, Arc::new(SolidColor::new(Color::new(amount, amount, amount))), mode)
+    }
+
+    pub fn textured(
+        a: Arc<dyn Material + Send + Sync>,
+        b: Arc<dyn Material + Send + Sync>,
+        amount: Arc<dyn Texture + Send + Sync>,
+        mode: MixMode,
+    ) -> Self {
+        Self {a, b, amount, mode, seed: 0}
+    }
+
+    fn amount(&self, rec: &HitRecord) -> f32 {
+        self.amount.value(rec.u, rec.v, &rec.p).x.clamp(0.0, 1.0)
+    }
+
+    /// The material used at `rec` when only one can be, the heavier one when
+    /// blending.
+    fn pick(&self, rec: &HitRecord) -> &Arc<dyn Material + Send + Sync> {
+        let threshold = match self.mode {
+            MixMode::Blend => 0.5,
+            MixMode::Stochastic => hit_hash(self.seed, PICK, rec),
+        };
+        if self.amount(rec) > threshold { &self.b } else { &self.a }
+    }
+}
+
+impl Material for MixMaterial {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color {
+        match self.mode {
+            MixMode::Blend => {
+                let t = self.amount(rec);
+                self.a.eval(wo, wi, rec) * (1.0 - t) + self.b.eval(wo, wi, rec) * t
+            },
+            MixMode::Stochastic => self.pick(rec).eval(wo, wi, rec),
+        }
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32 {
+        match self.mode {
+            MixMode::Blend => {
+                let t = self.amount(rec);
+                self.a.pdf(wo, wi, rec) * (1.0 - t) + self.b.pdf(wo, wi, rec) * t
+            },
+            MixMode::Stochastic => self.pick(rec).pdf(wo, wi, rec),
+        }
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, uc: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        if self.mode == MixMode::Stochastic {
+            return self.pick(rec).sample(wo, rec, uc, u);
+        }
+
+        // Sample one material by weight, reusing `uc` for its own lobes
+        let t = self.amount(rec);
+        let (mat, weight, uc) = if uc < 1.0 - t {
+            (&self.a, 1.0 - t, uc / (1.0 - t))
+        } else {
+            (&self.b, t, (uc - (1.0 - t)) / t)
+        };
+        let mut bs = mat.sample(wo, rec, uc.min(1.0 - f32::EPSILON), u)?;
+        if bs.specular {
+            // The other material has no delta lobe in that direction
+            bs.f *= weight;
+            bs.pdf *= weight;
+        } else {
+            bs.f = self.eval(wo, &bs.wi, rec);
+            bs.pdf = self.pdf(wo, &bs.wi, rec);
+        }
+        Some(bs)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        match self.mode {
+            MixMode::Blend => {
+                let t = self.amount(rec);
+                self.a.emitted(r_in, rec) * (1.0 - t) + self.b.emitted(r_in, rec) * t
+            },
+            MixMode::Stochastic => self.pick(rec).emitted(r_in, rec),
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.a.is_emissive() || self.b.is_emissive()
+    }
+
+    /// Absorption of both media, as the weight inside is unknown.
+    fn transmittance(&self, distance: f32) -> Color {
+        self.a.transmittance(distance) * self.b.transmittance(distance)
+    }
+
+    fn has_interior(&self) -> bool {
+        self.a.has_interior() || self.b.has_interior()
+    }
+
+    fn is_cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        self.pick(rec).is_cut_out(r, rec)
+    }
+
+    fn perturb_normal(&self, rec: &mut HitRecord) {
+        let mat = Arc::clone(self.pick(rec));
+        mat.perturb_normal(rec);
+    }
+}
+
+/// Cuts holes in a surface where `opacity` is 0, for leaves and fences
+/// modelled as textured quads. Partial opacity lets the matching fraction of
+/// rays through.
+pub struct Mask {
+    pub base: Arc<dyn Material + Send + Sync>,
+    /// Read from the first channel
+    pub opacity: Arc<dyn Texture + Send + Sync>,
+    /// Tells apart the cut-outs of nested masks
+    pub seed: u64,
+}
+
+impl Mask {
+    pub fn new(base: Arc<dyn Material + Send + Sync>, opacity: Arc<dyn Texture + Send + Sync>) -> Self {
+        Self {base, opacity, seed: 0}
+    }
+}
+
+impl Material for Mask {
+    forward_to_base!();
+
+    fn is_cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        let opacity = self.opacity.value(rec.u, rec.v, &rec.p).x;
+        hit_hash(self.seed, OPACITY, rec) >= opacity || self.base.is_cut_out(r, rec)
+    }
+
+    fn perturb_normal(&self, rec: &mut HitRecord) {
+        self.base.perturb_normal(rec);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conductor::{Conductor, MetalPreset};
+    use crate::coated::CoatedDiffuse;
+    use crate::test_util::check_bsdf;
+    use crate::objects::Lambertian;
+
+    fn grey(x: f32) -> Arc<SolidColor> {
+        Arc::new(SolidColor::new(Color::new(x, x, x)))
+    }
+
+    /// Fraction of hits where both `a` and `b` hold, which is a quarter when
+    /// they are independent choices made half of the time.
+    fn both(a: impl Fn(&HitRecord) -> bool, b: impl Fn(&HitRecord) -> bool) -> f32 {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let n = 10_000;
+        let count = (0..n)
+            .map(|i| HitRecord::new(Vec3::new(i as f32 * 0.01, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, mat.clone()))
+            .filter(|rec| a(rec) && b(rec))
+            .count();
+        count as f32 / n as f32
+    }
+
+    #[test]
+    fn nested_choices_are_independent() {
+        let lambertian = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mix = |seed| {
+            let b = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+            let mut mix = MixMaterial::new(lambertian.clone(), b, 0.5, MixMode::Stochastic);
+            mix.seed = seed;
+            mix
+        };
+        let (outer, inner) = (mix(1), mix(2));
+        let mask = Mask::new(lambertian.clone(), grey(0.5));
+        let r = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let picks_b = |mix: &MixMaterial, rec: &HitRecord| Arc::ptr_eq(mix.pick(rec), &mix.b);
+        let fraction = both(|rec| picks_b(&outer, rec), |rec| picks_b(&inner, rec));
+        assert!((fraction - 0.25).abs() < 0.02, "both mixes pick b {}", fraction);
+        let fraction = both(|rec| mask.is_cut_out(&r, rec), |rec| picks_b(&outer, rec));
+        assert!((fraction - 0.25).abs() < 0.02, "cut out and mix picks b {}", fraction);
+    }
+
+    #[test]
+    fn blend_is_consistent() {
+        check_bsdf(Arc::new(MixMaterial::new(
+            Arc::new(Lambertian::new(Color::new(0.2, 0.8, 0.2))),
+            Arc::new(Conductor::preset(MetalPreset::Copper, 0.25)),
+            0.3,
+            MixMode::Blend,
+        )));
+    }
+
+    #[test]
+    fn stochastic_mix_is_consistent() {
+        check_bsdf(Arc::new(MixMaterial::new(
+            Arc::new(Lambertian::new(Color::new(0.2, 0.8, 0.2))),
+            Arc::new(CoatedDiffuse::new(Color::new(0.5, 0.5, 0.5), 1.5, 0.3)),
+            0.5,
+            MixMode::Stochastic,
+        )));
+    }
+}
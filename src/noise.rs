@@ -0,0 +1,144 @@
+//! Ken Perlin's improved gradient noise, with fractal sums built on top.
+
+use rand::seq::SliceRandom;
+
+use crate::rng::SampleRng;
+use crate::vector::Point3;
+
+/// Gradient noise in about [-1, 1], zero on every integer lattice point.
+#[derive(Clone, Debug)]
+pub struct Perlin {
+    /// Permutation of 0..256 repeated twice, so lookups need no wrapping
+    perm: Vec<u8>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut perm: Vec<u8> = (0..=255).collect();
+        perm.shuffle(&mut SampleRng::seeded(seed));
+        perm.extend_from_within(..);
+        Self {perm}
+    }
+
+    pub fn noise(&self, p: &Point3) -> f32 {
+        let (xf, yf, zf) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (x, y, z) = (p.x - xf, p.y - yf, p.z - zf);
+        let (xi, yi, zi) = (xf as i64 & 255, yf as i64 & 255, zf as i64 & 255);
+        let perm = |i: i64| self.perm[i as usize] as i64;
+        let hash = |dx: i64, dy: i64, dz: i64| perm(perm(perm(xi + dx) + yi + dy) + zi + dz);
+
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+        let corner = |dx: i64, dy: i64, dz: i64| {
+            grad(hash(dx, dy, dz), x - dx as f32, y - dy as f32, z - dz as f32)
+        };
+        lerp(
+            w,
+            lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+            lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))),
+        )
+    }
+
+    /// Fractional Brownian motion: `octaves` layers of noise, each at twice
+    /// the frequency and half the amplitude of the previous one.
+    pub fn fbm(&self, p: &Point3, octaves: u32) -> f32 {
+        self.fractal_sum(p, octaves, |n| n)
+    }
+
+    /// Like `fbm` but summing absolute values, which gives creases where the
+    /// noise crosses zero. Always positive.
+    pub fn turbulence(&self, p: &Point3, octaves: u32) -> f32 {
+        self.fractal_sum(p, octaves, f32::abs)
+    }
+
+    fn fractal_sum(&self, p: &Point3, octaves: u32, f: impl Fn(f32) -> f32) -> f32 {
+        let mut sum = 0.0;
+        let mut point = *p;
+        let mut amplitude = 1.0;
+        for _ in 0..octaves.max(1) {
+            sum += amplitude * f(self.noise(&point));
+            amplitude *= 0.5;
+            point *= 2.0;
+        }
+        sum
+    }
+}
+
+/// Quintic curve with zero first and second derivatives at 0 and 1.
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// Dot product with one of the twelve edge directions of a cube.
+fn grad(hash: i64, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn random_points(n: usize, range: f32) -> Vec<Point3> {
+        let mut rng = SampleRng::seeded(17);
+        (0..n).map(|_| Point3::random(-range, range, &mut rng)).collect()
+    }
+
+    #[test]
+    fn stays_in_range() {
+        let perlin = Perlin::new(1);
+        for p in random_points(100_000, 50.0) {
+            let n = perlin.noise(&p);
+            assert!(n.abs() <= 1.05, "noise {} at {:?}", n, p);
+            let t = perlin.turbulence(&p, 4);
+            assert!((0.0..=2.0 * 1.05).contains(&t), "turbulence {} at {:?}", t, p);
+            assert!(perlin.fbm(&p, 4).abs() <= 2.0 * 1.05);
+        }
+    }
+
+    #[test]
+    fn zero_on_the_lattice() {
+        let perlin = Perlin::new(2);
+        for x in -3..3 {
+            for y in -3..3 {
+                for z in -3..3 {
+                    assert_eq!(perlin.noise(&Point3::new(x as f32, y as f32, z as f32)), 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_for_a_seed() {
+        let (a, b, c) = (Perlin::new(3), Perlin::new(3), Perlin::new(4));
+        let points = random_points(100, 10.0);
+        assert!(points.iter().all(|p| a.noise(p) == b.noise(p)));
+        assert!(points.iter().any(|p| a.noise(p) != c.noise(p)));
+    }
+
+    #[test]
+    fn negative_coordinates_wrap_with_the_lattice() {
+        // The lattice repeats every 256 cells, on both sides of zero
+        let perlin = Perlin::new(5);
+        let mut rng = SampleRng::seeded(6);
+        for _ in 0..1000 {
+            let p = Point3::new(rng.gen_range(-3.0..3.0), rng.gen_range(-3.0..3.0), rng.gen_range(-3.0..3.0));
+            let shifted = p - Point3::new(256.0, 512.0, 256.0);
+            assert!((perlin.noise(&p) - perlin.noise(&shifted)).abs() < 1e-3, "at {:?}", p);
+        }
+        // and is continuous across zero on every axis
+        let eps = 1e-4;
+        for axis in [Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), Point3::new(0.0, 0.0, 1.0)] {
+            let q = Point3::new(0.3, 0.6, 0.2);
+            let p = q - axis * axis.dot(&q);
+            let jump = perlin.noise(&(p + axis * eps)) - perlin.noise(&(p - axis * eps));
+            assert!(jump.abs() < 1e-2, "jump of {} across zero along {:?}", jump, axis);
+        }
+    }
+}
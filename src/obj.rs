@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::objects::*;
+use crate::texture::{ImageTexture, WrapMode};
+use crate::triangle::TriangleMesh;
+use crate::vector::{Point3, Vec3, Color};
+
+/// Material description from an MTL file, fields default as in the spec.
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub kd: Color,
+    pub ks: Color,
+    pub ke: Color,
+    pub ns: f32,
+    pub ni: f32,
+    pub d: f32,
+    pub illum: u32,
+    /// Diffuse colour image, replaces `kd`
+    pub map_kd: Option<PathBuf>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            kd: Color::new(0.8, 0.8, 0.8),
+            ks: Color::new(0.0, 0.0, 0.0),
+            ke: Color::new(0.0, 0.0, 0.0),
+            ns: 0.0,
+            ni: 1.0,
+            d: 1.0,
+            illum: 1,
+            map_kd: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Picks the closest of our materials: emitters become `DiffuseLight`,
+    /// transparent ones `Dielectric`, mostly specular ones `Metal` with the
+    /// fuzz derived from the Phong exponent, and everything else `Lambertian`.
+    pub fn to_material(&self) -> Result<Arc<dyn Material + Send + Sync>, Box<dyn Error>> {
+        let max = |c: Color| c.x.max(c.y).max(c.z);
+        let mat: Arc<dyn Material + Send + Sync> = if max(self.ke) > 0.0 {
+            Arc::new(DiffuseLight::new(self.ke))
+        } else if self.d < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let index = if self.ni > 1.0 { self.ni } else { 1.5 };
+            Arc::new(Dielectric::new(index))
+        } else if max(self.ks) > max(self.kd) {
+            let fuzz = f32::sqrt(2.0 / (self.ns + 2.0));
+            Arc::new(Metal::new(self.ks, fuzz))
+        } else if let Some(path) = &self.map_kd {
+            Arc::new(Lambertian::textured(Arc::new(ImageTexture::load(path, WrapMode::Repeat, true)?)))
+        } else {
+            Arc::new(Lambertian::new(self.kd))
+        };
+        Ok(mat)
+    }
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, Box<dyn Error>> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read material file '{}': {}", path.display(), e))?;
+    let mut materials = parse_mtl(&source).map_err(|e| format!("{}:{}", path.display(), e))?;
+
+    // Texture paths are relative to the material file
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    for mat in materials.values_mut() {
+        mat.map_kd = mat.map_kd.take().map(|p| base_dir.join(p));
+    }
+    Ok(materials)
+}
+
+pub fn parse_mtl(source: &str) -> Result<HashMap<String, MtlMaterial>, String> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_no, line) in source.lines().enumerate() {
+        let err = |msg: &str| format!("{}: {}", line_no + 1, msg);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let name = args.first().ok_or_else(|| err("missing material name"))?;
+            if let Some((name, mat)) = current.take() {
+                materials.insert(name, mat);
+            }
+            current = Some((name.to_string(), MtlMaterial::default()));
+            continue;
+        }
+
+        let mat = match current.as_mut() {
+            Some((_, mat)) => mat,
+            None => return Err(err(&format!("'{}' before any 'newmtl'", keyword))),
+        };
+        match keyword {
+            "Kd" => mat.kd = parse_color(&args).map_err(|e| err(&e))?,
+            "Ks" => mat.ks = parse_color(&args).map_err(|e| err(&e))?,
+            "Ke" => mat.ke = parse_color(&args).map_err(|e| err(&e))?,
+            "Ns" => mat.ns = parse_float(&args).map_err(|e| err(&e))?,
+            "Ni" => mat.ni = parse_float(&args).map_err(|e| err(&e))?,
+            "d" => mat.d = parse_float(&args).map_err(|e| err(&e))?,
+            "Tr" => mat.d = 1.0 - parse_float(&args).map_err(|e| err(&e))?,
+            // Options come before the file name
+            "map_Kd" => {
+                let file = args.last().ok_or_else(|| err("missing texture file"))?;
+                mat.map_kd = Some(PathBuf::from(file));
+            },
+            "illum" => {
+                mat.illum = args.first()
+                    .and_then(|a| a.parse().ok())
+                    .ok_or_else(|| err("expected an integer"))?;
+            },
+            // Other textures and statements are not supported
+            _ => {},
+        }
+    }
+    if let Some((name, mat)) = current {
+        materials.insert(name, mat);
+    }
+    Ok(materials)
+}
+
+/// Loads every face of an OBJ file, one mesh per group and material. Faces
+/// use `material` if given, otherwise the MTL files referenced by the model.
+/// Missing libraries and unknown materials only warn and use a grey default.
+pub fn load(
+    path: &Path,
+    material: Option<Arc<dyn Material + Send + Sync>>,
+) -> Result<ObjModel, Box<dyn Error>> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read model '{}': {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let mut model = parse(&source, base_dir, material).map_err(|e| format!("{}:{}", path.display(), e))?;
+    for warning in model.warnings.iter_mut() {
+        *warning = format!("{}:{}", path.display(), warning);
+    }
+    Ok(model)
+}
+
+/// Meshes of an OBJ file and the problems that were worked around.
+pub struct ObjModel {
+    pub meshes: Vec<TriangleMesh>,
+    /// One line each, for the caller to report
+    pub warnings: Vec<String>,
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    mat: Option<Arc<dyn Material + Send + Sync>>,
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    positions: Vec<Point3>,
+    normals: Vec<Option<Vec3>>,
+    uvs: Vec<Option<[f32; 2]>>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn vertex(
+        &mut self,
+        key: (usize, Option<usize>, Option<usize>),
+        positions: &[Point3],
+        uvs: &[[f32; 2]],
+        normals: &[Vec3],
+    ) -> usize {
+        *self.vertices.entry(key).or_insert_with(|| {
+            self.positions.push(positions[key.0]);
+            self.uvs.push(key.1.map(|i| uvs[i]));
+            self.normals.push(key.2.map(|i| normals[i]));
+            self.positions.len() - 1
+        })
+    }
+
+    fn build(self, default: &Arc<dyn Material + Send + Sync>) -> Option<TriangleMesh> {
+        if self.indices.is_empty() {
+            return None;
+        }
+
+        // Attributes are only kept if every vertex has them
+        let normals: Option<Vec<Vec3>> = self.normals.into_iter().collect();
+        let uvs: Option<Vec<[f32; 2]>> = self.uvs.into_iter().collect();
+        let mesh = TriangleMesh::new(
+            self.positions,
+            normals,
+            uvs,
+            self.indices,
+            self.mat.unwrap_or_else(|| Arc::clone(default)),
+        ).expect("OBJ faces are resolved against the vertex lists");
+        Some(mesh)
+    }
+}
+
+pub fn parse(
+    source: &str,
+    base_dir: &Path,
+    material: Option<Arc<dyn Material + Send + Sync>>,
+) -> Result<ObjModel, String> {
+    let default: Arc<dyn Material + Send + Sync> = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8)));
+    let mut library: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut cache: HashMap<String, Arc<dyn Material + Send + Sync>> = HashMap::new();
+
+    let mut positions: Vec<Point3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<[f32; 2]> = vec![];
+    let mut meshes = vec![];
+    let mut warnings = vec![];
+    let mut current = MeshBuilder {mat: material.clone(), ..Default::default()};
+
+    for (line_no, line) in source.lines().enumerate() {
+        let err = |msg: &str| format!("{}: {}", line_no + 1, msg);
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&args).map_err(|e| err(&e))?),
+            "vn" => normals.push(parse_vec3(&args).map_err(|e| err(&e))?),
+            "vt" => {
+                let u = args.first().and_then(|a| a.parse().ok());
+                let v = args.get(1).map_or(Some(0.0), |a| a.parse().ok());
+                match (u, v) {
+                    (Some(u), Some(v)) => uvs.push([u, v]),
+                    _ => return Err(err("expected texture coordinates")),
+                }
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(err("faces need at least three vertices"));
+                }
+                let mut face = vec![];
+                for arg in args.iter() {
+                    let key = parse_face_vertex(arg, positions.len(), uvs.len(), normals.len())
+                        .map_err(|e| err(&e))?;
+                    face.push(current.vertex(key, &positions, &uvs, &normals));
+                }
+
+                // Fan triangulation, fine for the convex polygons exporters write
+                for k in 1..face.len() - 1 {
+                    current.indices.push([face[0], face[k], face[k + 1]]);
+                }
+            },
+            "g" | "o" => {
+                let mat = current.mat.clone();
+                meshes.extend(std::mem::take(&mut current).build(&default));
+                current.mat = mat;
+            },
+            "usemtl" => {
+                let name = args.first().ok_or_else(|| err("missing material name"))?;
+                meshes.extend(std::mem::take(&mut current).build(&default));
+                current.mat = match &material {
+                    Some(mat) => Some(Arc::clone(mat)),
+                    None => {
+                        // Exports often reference materials they do not ship,
+                        // warn once per name and use the default instead
+                        if !cache.contains_key(*name) {
+                            let mat = match library.get(*name).map(|mtl| mtl.to_material()) {
+                                Some(Ok(mat)) => mat,
+                                Some(Err(e)) => {
+                                    warnings.push(err(&format!("{}, using the default material", e)));
+                                    Arc::clone(&default)
+                                },
+                                None => {
+                                    warnings.push(err(&format!(
+                                        "unknown material '{}', using the default", name
+                                    )));
+                                    Arc::clone(&default)
+                                },
+                            };
+                            cache.insert(name.to_string(), mat);
+                        }
+                        Some(Arc::clone(&cache[*name]))
+                    },
+                };
+            },
+            // Material libraries are not needed with an override
+            "mtllib" if material.is_none() => {
+                for file in args.iter() {
+                    match load_mtl(&base_dir.join(file)) {
+                        Ok(mtl) => library.extend(mtl),
+                        Err(e) => warnings.push(err(&e.to_string())),
+                    }
+                }
+            },
+            // Smoothing groups, lines, points and others are ignored
+            _ => {},
+        }
+    }
+    meshes.extend(current.build(&default));
+    Ok(ObjModel {meshes, warnings})
+}
+
+fn parse_face_vertex(
+    arg: &str,
+    num_positions: usize,
+    num_uvs: usize,
+    num_normals: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    // Indices start at 1, negative ones count back from the last element
+    let resolve = |s: &str, len: usize| -> Result<Option<usize>, String> {
+        if s.is_empty() {
+            return Ok(None);
+        }
+        let i: i64 = s.parse().map_err(|_| format!("invalid index '{}'", s))?;
+        let index = if i > 0 { i - 1 } else { len as i64 + i };
+        if i == 0 || index < 0 || index >= len as i64 {
+            return Err(format!("index {} out of range", i));
+        }
+        Ok(Some(index as usize))
+    };
+
+    let mut parts = arg.split('/');
+    let v = resolve(parts.next().unwrap_or(""), num_positions)?
+        .ok_or_else(|| format!("missing position index in '{}'", arg))?;
+    let vt = resolve(parts.next().unwrap_or(""), num_uvs)?;
+    let vn = resolve(parts.next().unwrap_or(""), num_normals)?;
+    Ok((v, vt, vn))
+}
+
+fn parse_float(args: &[&str]) -> Result<f32, String> {
+    args.first()
+        .and_then(|a| a.parse().ok())
+        .ok_or_else(|| "expected a number".to_string())
+}
+
+fn parse_vec3(args: &[&str]) -> Result<Vec3, String> {
+    let values: Vec<f32> = args.iter()
+        .take(3)
+        .map(|a| a.parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| "expected three numbers".to_string())?;
+    if values.len() < 3 {
+        return Err("expected three numbers".to_string());
+    }
+    Ok(Vec3::new(values[0], values[1], values[2]))
+}
+
+fn parse_color(args: &[&str]) -> Result<Color, String> {
+    // A single value means a grey colour
+    match args.len() {
+        1 => parse_float(args).map(|v| Color::new(v, v, v)),
+        _ => parse_vec3(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_materials_fall_back_to_default() {
+        let source = "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl nowhere\nf 1 2 3\n";
+        let model = parse(source, Path::new("/nonexistent"), None).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].indices, vec![[0, 1, 2]]);
+        assert_eq!(model.warnings.len(), 2);
+        assert!(model.warnings[0].starts_with("1: "), "{}", model.warnings[0]);
+        assert_eq!(model.warnings[1], "5: unknown material 'nowhere', using the default");
+    }
+}
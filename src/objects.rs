@@ -1,19 +1,69 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use rand::{Rng, thread_rng};
 use crate::vector::*;
 use crate::ray::*;
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::triangle::TriangleMesh;
+use crate::lights::Light;
+use crate::environment::Background;
+use crate::sky::PhysicalSky;
+use crate::texture::{SolidColor, Texture};
+use crate::sampling::{self, Onb};
+use crate::microfacet::fresnel_dielectric;
+use crate::glass;
+use crate::utilities::PI;
 
 pub struct HitRecord {
     pub p: Point3,
     pub n: Vec3,
     pub t : f32,
+    /// Surface coordinates for texture lookups
+    pub u: f32,
+    pub v: f32,
+    /// Tangent frame, derivatives of `p` along `u` and `v`
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front: bool,
     pub mat: Arc<dyn Material>,
+    /// Object that was hit, for shapes that can be sampled as lights
+    pub object: Option<ObjectId>,
+}
+
+/// Identity of a hittable, taken from its address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjectId(usize);
+
+impl ObjectId {
+    pub fn of<T: ?Sized>(object: &T) -> Self {
+        Self(object as *const T as *const () as usize)
+    }
 }
 
 impl HitRecord {
+    /// Record with an arbitrary tangent frame around `n`, shapes with
+    /// texture coordinates replace it.
     pub fn new(p: Point3, n: Vec3, t: f32, mat: Arc<dyn Material>) -> Self {
-        Self {p, n, t, front: true, mat}
+        let frame = Onb::from_w(n);
+        Self {p, n, t, u: 0.0, v: 0.0, dpdu: frame.u, dpdv: frame.v, front: true, mat, object: None}
+    }
+
+    /// Lets the material change the shading normal, see
+    /// `Material::perturb_normal`.
+    pub fn perturb_normal(&mut self) {
+        let mat = Arc::clone(&self.mat);
+        mat.perturb_normal(self);
+    }
+
+    /// Orthonormal frame around the shading normal, with `u` following `dpdu`
+    /// and `v` as close to `dpdv` as possible.
+    pub fn shading_frame(&self) -> Onb {
+        let n = self.n;
+        let t = (self.dpdu - n * n.dot(&self.dpdu)).normalize();
+        let t = if t.near_zero() || t.x.is_nan() { Onb::from_w(n).u } else { t };
+        let b = self.dpdv - n * n.dot(&self.dpdv) - t * t.dot(&self.dpdv);
+        let b = if b.near_zero() { n.cross(&t) } else { b.normalize() };
+        Onb {u: t, v: b, w: n}
     }
 
     pub fn set_face_normal(&mut self, r: &Ray) {
@@ -24,42 +74,204 @@ impl HitRecord {
 
 pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Whether the surface emits light and should be sampled as a light.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Direction from `origin` towards a point on the surface, for light
+    /// sampling. `None` if the surface cannot be sampled from there.
+    fn sample_direction(&self, _origin: &Point3, _u: [f32; 2]) -> Option<Vec3> {
+        None
+    }
+
+    /// Solid angle density of `sample_direction` returning `direction`.
+    fn pdf_direction(&self, _origin: &Point3, _direction: &Vec3) -> f32 {
+        0.0
+    }
 }
 
 pub struct Scene {
     pub objects: Vec<Arc<dyn Hittable + Send + Sync>>,
+    pub background: Background,
+    bvh: Option<BvhNode>,
+    /// Point, spot and directional lights, reached only by shadow rays
+    pub lights: Vec<Arc<dyn Light + Send + Sync>>,
+    unbounded: Vec<Arc<dyn Hittable + Send + Sync>>,
+    emitters: Vec<Arc<dyn Hittable + Send + Sync>>,
+    /// Position of every emitter in `emitters`
+    emitter_index: HashMap<ObjectId, usize>,
+    /// Position in `lights` of the sun added by `set_physical_sky`
+    sun: Option<usize>,
 }
 
 impl Scene {
+    pub fn new(objects: Vec<Arc<dyn Hittable + Send + Sync>>) -> Self {
+        Self {
+            objects,
+            background: Background::sky(),
+            lights: vec![],
+            bvh: None,
+            unbounded: vec![],
+            emitters: vec![],
+            emitter_index: HashMap::new(),
+            sun: None,
+        }
+    }
+
     pub fn add(&mut self, object: Arc<dyn Hittable + Send + Sync>) {
         self.objects.push(Arc::clone(&object));
+        self.bvh = None;
+        self.unbounded.clear();
+        self.emitters.clear();
+        self.emitter_index.clear();
+    }
+
+    pub fn add_light(&mut self, light: Arc<dyn Light + Send + Sync>) {
+        self.lights.push(light);
+    }
+
+    /// Replaces the background, along with the sun of a physical sky.
+    pub fn set_background(&mut self, background: Background) {
+        if let Some(i) = self.sun.take() {
+            self.lights.remove(i);
+        }
+        self.background = background;
+    }
+
+    /// Uses `sky` as the background and adds its sun.
+    pub fn set_physical_sky(&mut self, sky: PhysicalSky) {
+        let sun = Arc::new(sky.sun());
+        self.set_background(Background::Physical(sky));
+        self.sun = Some(self.lights.len());
+        self.add_light(sun);
+    }
+
+    /// Adds every face of `mesh` as a separate object.
+    pub fn add_mesh(&mut self, mesh: Arc<TriangleMesh>) {
+        for triangle in mesh.triangles() {
+            self.add(triangle);
+        }
+    }
+
+    /// Builds the acceleration structure used by `hit`. Objects without a
+    /// bounding box are kept aside and tested linearly. Also collects the
+    /// emissive objects used for light sampling.
+    pub fn build_bvh(&mut self) {
+        let (mut bounded, unbounded): (Vec<_>, Vec<_>) = self.objects.iter()
+            .cloned()
+            .partition(|o| o.bounding_box().is_some());
+        self.bvh = if bounded.is_empty() { None } else { Some(BvhNode::new(&mut bounded)) };
+        self.unbounded = unbounded;
+        self.emitters = self.objects.iter().filter(|o| o.is_emissive()).cloned().collect();
+        self.emitter_index = self.emitters.iter()
+            .enumerate()
+            .map(|(i, e)| (ObjectId::of(e.as_ref()), i))
+            .collect();
+    }
+
+    /// Whether `sample_light` has anything to sample, emitters or the
+    /// background.
+    pub fn has_sampled_lights(&self) -> bool {
+        self.sampled_light_count() > 0
+    }
+
+    fn sampled_light_count(&self) -> usize {
+        self.emitters.len() + self.background.is_sampled() as usize
+    }
+
+    /// Direction towards one of the emitters or the background, picked
+    /// uniformly with `uc`, along with the emitter or `None` for the
+    /// background. The sample only counts if that light is what the
+    /// direction reaches first.
+    pub fn sample_light(&self, origin: &Point3, uc: f32, u: [f32; 2]) -> Option<(Vec3, Option<ObjectId>)> {
+        let count = self.sampled_light_count();
+        if count == 0 {
+            return None;
+        }
+        let index = ((uc * count as f32) as usize).min(count - 1);
+        match self.emitters.get(index) {
+            Some(emitter) => Some((emitter.sample_direction(origin, u)?, Some(ObjectId::of(emitter.as_ref())))),
+            None => Some((self.background.sample(u)?, None)),
+        }
+    }
+
+    /// Density of `sample_light` returning `direction` towards what it
+    /// reaches first, the object in `hit` or the background if `None`. Only
+    /// that light is evaluated, so this does not grow with the number of
+    /// emitters.
+    pub fn light_pdf(&self, origin: &Point3, direction: &Vec3, hit: Option<&HitRecord>) -> f32 {
+        let count = self.sampled_light_count();
+        if count == 0 {
+            return 0.0;
+        }
+        let pdf = match hit {
+            Some(rec) => match rec.object.and_then(|id| self.emitter_index.get(&id)) {
+                Some(&i) => self.emitters[i].pdf_direction(origin, direction),
+                None => 0.0,
+            },
+            None => self.background.pdf(direction),
+        };
+        pdf / count as f32
     }
 
     pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        // Go on past the cut-out parts of masked surfaces
+        let mut t_min = t_min;
+        loop {
+            let rec = self.closest_hit(r, t_min, t_max)?;
+            if !rec.mat.is_cut_out(r, &rec) {
+                return Some(rec);
+            }
+            t_min = rec.t + 0.001;
+        }
+    }
+
+    fn closest_hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        // Fall back to testing every object if there is no BVH
+        let (bvh, linear) = match &self.bvh {
+            Some(bvh) => (Some(bvh), &self.unbounded),
+            None => (None, &self.objects),
+        };
+
         let mut closest_so_far = t_max;
         let mut hit_rec: Option<HitRecord> = None;
-        for object in self.objects.iter() {
-            match object.hit(r, t_min, t_max) {
-                Some(rec) => {
-                    if rec.t < closest_so_far {
-                        closest_so_far = rec.t;
-                        hit_rec = Some(rec);
-                    }
-                },
-                None => { },
-            };
+        if let Some(rec) = bvh.and_then(|bvh| bvh.hit(r, t_min, closest_so_far)) {
+            closest_so_far = rec.t;
+            hit_rec = Some(rec);
+        }
+        for object in linear.iter() {
+            if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                hit_rec = Some(rec);
+            }
         }
         hit_rec
     }
 }
 
-pub struct Sphere<T: Material> {
+impl Hittable for Scene {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        Scene::hit(self, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.objects.iter()
+            .map(|o| o.bounding_box())
+            .reduce(|a, b| Some(a?.surrounding(&b?)))
+            .flatten()
+    }
+}
+
+pub struct Sphere {
     pub centre: Point3,
     pub radius: f32,
-    pub mat: Arc<T>,
+    pub mat: Arc<dyn Material + Send + Sync>,
 }
 
-impl<T: Material + 'static> Hittable for Sphere<T> {
+impl Hittable for Sphere {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let oc = r.origin - self.centre;
         let a = r.direction.length_squared();
@@ -69,7 +281,7 @@ impl<T: Material + 'static> Hittable for Sphere<T> {
 
         // Stop if we did not hit the sphere
         if disc < 0.0 {
-            return None;
+            None
 
         // Check if the hit is in the given range
         } else {
@@ -92,98 +304,371 @@ impl<T: Material + 'static> Hittable for Sphere<T> {
                 t,
                 Arc::clone(&self.mat) as Arc<dyn Material>
             );
+            let q = (p - self.centre) / self.radius.abs();
+            (rec.u, rec.v) = sphere_uv(&q);
+            (rec.dpdu, rec.dpdv) = self.sphere_tangents(&q);
+            rec.object = Some(ObjectId::of(self));
             rec.set_face_normal(r);
-            return Some(rec);
+            Some(rec)
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+        Some(Aabb::new(self.centre - r, self.centre + r))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat.is_emissive()
+    }
+
+    fn sample_direction(&self, origin: &Point3, u: [f32; 2]) -> Option<Vec3> {
+        // Uniform over the cone the sphere subtends, none from the inside
+        let cos_theta_max = self.cos_theta_max(origin)?;
+        let dir = sampling::uniform_cone(u, cos_theta_max);
+        Some(Onb::from_w((self.centre - *origin).normalize()).to_world(dir))
+    }
+
+    fn pdf_direction(&self, origin: &Point3, direction: &Vec3) -> f32 {
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max) if self.hit(&Ray::new(*origin, *direction), 0.001, f32::INFINITY).is_some() => {
+                sampling::uniform_cone_pdf(cos_theta_max)
+            },
+            _ => 0.0,
+        }
+    }
+}
+
+/// Longitude and latitude of a point on the unit sphere, `v` goes from the
+/// bottom (-y) to the top and `u` around from -x.
+fn sphere_uv(p: &Point3) -> (f32, f32) {
+    let theta = f32::acos(-p.y.clamp(-1.0, 1.0));
+    let phi = f32::atan2(-p.z, p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+impl Sphere {
+    /// Takes the material as a concrete `Arc`, so scenes can share one
+    /// between spheres without naming the trait object type.
+    pub fn new<M: Material + Send + Sync + 'static>(centre: Point3, radius: f32, mat: Arc<M>) -> Self {
+        Self {centre, radius, mat}
+    }
+
+    /// Derivatives of the `sphere_uv` parameterization at the unit vector
+    /// `q` from the centre.
+    fn sphere_tangents(&self, q: &Vec3) -> (Vec3, Vec3) {
+        let r = self.radius.abs();
+        // Distance from the y axis, kept away from zero at the poles
+        let rho = f32::sqrt(q.x * q.x + q.z * q.z).max(1e-4);
+        let dpdu = Vec3::new(q.z, 0.0, -q.x) * (2.0 * PI * r);
+        let dpdv = Vec3::new(-q.y * q.x / rho, rho, -q.y * q.z / rho) * (PI * r);
+        (dpdu, dpdv)
+    }
+
+    fn cos_theta_max(&self, origin: &Point3) -> Option<f32> {
+        let sin2 = self.radius * self.radius / (self.centre - *origin).length_squared();
+        if sin2 >= 1.0 {
+            return None;
+        }
+        // Keep 1 - cos away from zero for small or distant spheres
+        let cos_theta_max = if sin2 < 1e-4 { 1.0 - sin2 / 2.0 } else { f32::sqrt(1.0 - sin2) };
+        Some(f32::min(cos_theta_max, 1.0 - 1e-7))
+    }
+}
+
+/// Result of sampling a BSDF. Directions point away from the surface.
+pub struct BsdfSample {
+    pub wi: Vec3,
+    /// BSDF value, for specular lobes it includes the delta term
+    pub f: Color,
+    /// Solid angle density, or the lobe probability for specular lobes
+    pub pdf: f32,
+    pub specular: bool,
+}
+
+impl BsdfSample {
+    /// Throughput factor f * |cos| / pdf of the sample.
+    pub fn weight(&self, n: &Vec3) -> Color {
+        self.f * (self.wi.dot(n).abs() / self.pdf)
+    }
 }
 
+/// Surface scattering in terms of its BSDF. `wo` points towards the viewer
+/// and `wi` towards the light, both normalized and away from the surface.
 pub trait Material {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> Option<Ray>;
+    fn eval(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color;
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32;
+
+    /// Picks `wi` using `uc` to choose a lobe and `u` for the direction.
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, uc: f32, u: [f32; 2]) -> Option<BsdfSample>;
+
+    /// Radiance emitted towards the incoming ray, black for most materials
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Fraction of light left after travelling `distance` inside the object,
+    /// for absorbing media such as tinted glass. Paths only know they are
+    /// inside once they went through the front face, so closed surfaces
+    /// whose normals point outwards and `has_interior` are needed.
+    fn transmittance(&self, _distance: f32) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    /// Whether the surface bounds a volume that paths go into when they
+    /// pass through it, false for thin sheets and opaque materials.
+    fn has_interior(&self) -> bool {
+        false
+    }
+
+    /// Whether `r` goes on through the surface at `rec` as if it was not
+    /// there, for cut-outs.
+    fn is_cut_out(&self, _r: &Ray, _rec: &HitRecord) -> bool {
+        false
+    }
+
+    /// Changes the shading normal in `rec` before the BSDF is used, for
+    /// normal and bump mapping.
+    fn perturb_normal(&self, _rec: &mut HitRecord) {}
+}
+
+/// Forwards the scattering of a wrapper material to its `base`, leaving
+/// `is_cut_out` and `perturb_normal` to the wrapper.
+macro_rules! forward_to_base {
+    () => {
+        fn eval(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color {
+            self.base.eval(wo, wi, rec)
+        }
+
+        fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32 {
+            self.base.pdf(wo, wi, rec)
+        }
+
+        fn sample(&self, wo: &Vec3, rec: &HitRecord, uc: f32, u: [f32; 2]) -> Option<BsdfSample> {
+            self.base.sample(wo, rec, uc, u)
+        }
+
+        fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+            self.base.emitted(r_in, rec)
+        }
+
+        fn is_emissive(&self) -> bool {
+            self.base.is_emissive()
+        }
+
+        fn transmittance(&self, distance: f32) -> Color {
+            self.base.transmittance(distance)
+        }
+
+        fn has_interior(&self) -> bool {
+            self.base.has_interior()
+        }
+    };
 }
+pub(crate) use forward_to_base;
 
 pub struct Lambertian {
-    pub albedo: Color,
+    pub albedo: Arc<dyn Texture + Send + Sync>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture + Send + Sync>) -> Self {
         Self {albedo}
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, attenuation: &mut Color) -> Option<Ray> {
-        *attenuation = self.albedo;
-        let mut scatter_dir = rec.n + Vec3::unit_random();
-        if scatter_dir.near_zero() {
-            scatter_dir = rec.n;
+    fn eval(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color {
+        if wo.dot(&rec.n) > 0.0 && wi.dot(&rec.n) > 0.0 {
+            self.albedo.value(rec.u, rec.v, &rec.p) / PI
+        } else {
+            Color::new(0.0, 0.0, 0.0)
         }
-        Some(Ray::new(rec.p, scatter_dir))
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32 {
+        if wo.dot(&rec.n) > 0.0 {
+            sampling::cosine_hemisphere_pdf(wi.dot(&rec.n))
+        } else {
+            0.0
+        }
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, _uc: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        // Cosine-weighted directions cancel the cosine term of the estimator
+        let wi = Onb::from_w(rec.n).to_world(sampling::cosine_hemisphere(u));
+        let pdf = self.pdf(wo, &wi, rec);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {wi, f: self.eval(wo, &wi, rec), pdf, specular: false})
     }
 }
 
+/// Fuzzy mirror. The perturbed reflection has no tractable density, so the
+/// whole lobe is treated as specular.
 pub struct Metal {
-    pub albedo: Color,
-    pub fuzz: f32,
+    pub albedo: Arc<dyn Texture + Send + Sync>,
+    /// Roughness, read from the first channel
+    pub fuzz: Arc<dyn Texture + Send + Sync>,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzzy: f32) -> Self {
-        let fuzz = if fuzzy >= 0.0 && fuzzy < 1.0 { fuzzy } else {1.0};
+        let fuzz = if (0.0..1.0).contains(&fuzzy) { fuzzy } else {1.0};
+        Self::textured(
+            Arc::new(SolidColor::new(albedo)),
+            Arc::new(SolidColor::new(Color::new(fuzz, fuzz, fuzz))),
+        )
+    }
+
+    pub fn textured(
+        albedo: Arc<dyn Texture + Send + Sync>,
+        fuzz: Arc<dyn Texture + Send + Sync>,
+    ) -> Self {
         Metal {albedo, fuzz}
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Vec3) -> Option<Ray> {
-        *attenuation = self.albedo;
-        let reflected = reflect(&r_in.direction.normalize(), &rec.n);
-        let scattered = Ray::new(rec.p, reflected + Vec3::unit_random() * self.fuzz);
-        if scattered.direction.dot(&rec.n) > 0.0 {
-            Some(scattered)
-        } else {
-            None
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> f32 {
+        0.0
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, _uc: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        let reflected = reflect(&-*wo, &rec.n);
+        let fuzz = self.fuzz.value(rec.u, rec.v, &rec.p).x.clamp(0.0, 1.0);
+        let wi = (reflected + sampling::uniform_sphere(u) * fuzz).normalize();
+
+        // Rays scattered below the surface are absorbed
+        let cos_theta = wi.dot(&rec.n);
+        if cos_theta <= 0.0 {
+            return None;
         }
+        let albedo = self.albedo.value(rec.u, rec.v, &rec.p);
+        Some(BsdfSample {wi, f: albedo / cos_theta, pdf: 1.0, specular: true})
     }
 }
 
+/// Smooth glass, optionally absorbing light inside it.
 pub struct Dielectric {
     pub index: f32,
+    /// Beer-Lambert absorption coefficient per unit distance
+    pub absorption: Color,
 }
 
 impl Dielectric {
     pub fn new(index: f32) -> Self {
-        Dielectric {index}
+        Dielectric {index, absorption: Color::new(0.0, 0.0, 0.0)}
     }
-}
 
-fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
-    // Schlick's approximation
-    let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-    let r0 = r0 * r0;
-    r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
+    /// Glass that lets `tint` through after `distance` inside it.
+    pub fn tinted(index: f32, tint: Color, distance: f32) -> Self {
+        Dielectric {index, absorption: glass::absorption_for_tint(&tint, distance)}
+    }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Vec3) -> Option<Ray> {
-        // Attenuation color
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
 
-        // Scattered ray
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> f32 {
+        0.0
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, uc: f32, _u: [f32; 2]) -> Option<BsdfSample> {
         let relative_index = if rec.front { 1.0 / self.index } else { self.index };
-        let unit_dir = r_in.direction.normalize();
-        let cos_theta = -unit_dir.dot(&rec.n);
-        let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
-
-        // Ray can be refracted or reflected
-        let can_refract = relative_index * sin_theta < 1.0;
-        let rand_refract = reflectance(cos_theta, relative_index) < thread_rng().gen();
-        let direction = if can_refract && rand_refract {
-            refract(&unit_dir, &rec.n, relative_index)
+        let unit_dir = -*wo;
+        let cos_theta = f32::min(wo.dot(&rec.n), 1.0);
+
+        // Ray can be refracted or reflected, picked in proportion to Fresnel
+        let r = fresnel_dielectric(cos_theta, 1.0 / relative_index);
+        let (wi, pdf) = if uc < r {
+            (reflect(&unit_dir, &rec.n), r)
         } else {
-            reflect(&unit_dir, &rec.n)
+            (refract(&unit_dir, &rec.n, relative_index), 1.0 - r)
         };
-        Some(Ray::new(rec.p, direction))
+        let f = Color::new(1.0, 1.0, 1.0) * (pdf / wi.dot(&rec.n).abs());
+        Some(BsdfSample {wi, f, pdf, specular: true})
+    }
+
+    fn transmittance(&self, distance: f32) -> Color {
+        glass::beer_lambert(&self.absorption, distance)
+    }
+
+    fn has_interior(&self) -> bool {
+        true
+    }
+}
+
+pub struct DiffuseLight {
+    pub emit: Arc<dyn Texture + Send + Sync>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn textured(emit: Arc<dyn Texture + Send + Sync>) -> Self {
+        Self {emit}
+    }
+}
+
+impl Material for DiffuseLight {
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> f32 {
+        0.0
+    }
+
+    fn sample(&self, _wo: &Vec3, _rec: &HitRecord, _uc: f32, _u: [f32; 2]) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        // Lights only emit from their outer side
+        if rec.front { self.emit.value(rec.u, rec.v, &rec.p) } else { Color::new(0.0, 0.0, 0.0) }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::check_bsdf;
+
+    #[test]
+    fn lambertian_is_consistent() {
+        check_bsdf(Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8))));
+    }
+
+    #[test]
+    fn physical_sky_keeps_a_single_sun() {
+        let mut scene = Scene::new(vec![]);
+        scene.set_physical_sky(PhysicalSky::new(30.0, 0.0, 3.0, 1.0));
+        scene.set_physical_sky(PhysicalSky::new(60.0, 90.0, 3.0, 1.0));
+        assert_eq!(scene.lights.len(), 1);
+        scene.set_background(Background::Solid(Color::new(0.0, 0.0, 0.0)));
+        assert!(scene.lights.is_empty());
     }
 }
@@ -0,0 +1,367 @@
+//! One material for most surfaces, after Burley's "Physically Based Shading
+//! at Disney" (2012) and its 2015 extension to transmission.
+
+use std::sync::Arc;
+
+use crate::glass;
+use crate::microfacet::{fresnel_dielectric, refract, TrowbridgeReitz};
+use crate::objects::{BsdfSample, HitRecord, Material};
+use crate::ray::Ray;
+use crate::sampling;
+use crate::texture::{SolidColor, Texture};
+use crate::utilities::PI;
+use crate::vector::{Color, Vec3};
+
+/// Blend of a diffuse base with sheen, a GGX specular lobe tinted by the
+/// base colour as it becomes metallic, rough glass and a clear coat. Scalar
+/// parameters are read from the first channel of their textures and go from
+/// 0 to 1. Roughness is kept above a small minimum, so every lobe has a
+/// density and works with light sampling, except for the glass, which turns
+/// into a smooth dielectric near zero like `RoughDielectric`.
+pub struct Principled {
+    pub base_color: Arc<dyn Texture + Send + Sync>,
+    pub metallic: Arc<dyn Texture + Send + Sync>,
+    pub roughness: Arc<dyn Texture + Send + Sync>,
+    /// Reflectance of the dielectric base, 0.5 is 4% at normal incidence
+    pub specular: Arc<dyn Texture + Send + Sync>,
+    /// White grazing highlight, for cloth
+    pub sheen: Arc<dyn Texture + Send + Sync>,
+    pub clearcoat: Arc<dyn Texture + Send + Sync>,
+    pub clearcoat_roughness: f32,
+    /// Fraction of the dielectric base that is glass rather than diffuse
+    pub transmission: Arc<dyn Texture + Send + Sync>,
+    /// Index of refraction of the glass
+    pub index: f32,
+    pub emission: Option<Arc<dyn Texture + Send + Sync>>,
+}
+
+/// Parameters looked up at a hit point.
+struct Params {
+    base_color: Color,
+    metallic: f32,
+    roughness: f32,
+    /// Roughness of the glass, not kept above the minimum
+    glass_roughness: f32,
+    specular: f32,
+    sheen: f32,
+    clearcoat: f32,
+    transmission: f32,
+}
+
+/// Probabilities of sampling each lobe.
+struct LobeWeights {
+    diffuse: f32,
+    specular: f32,
+    transmission: f32,
+    clearcoat: f32,
+}
+
+const MIN_ROUGHNESS: f32 = 0.045;
+
+impl Principled {
+    /// Dielectric with the defaults of Disney's model, roughness 0.5 and
+    /// specular 0.5.
+    pub fn new(base_color: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(base_color)))
+    }
+
+    pub fn textured(base_color: Arc<dyn Texture + Send + Sync>) -> Self {
+        let grey = |x: f32| -> Arc<dyn Texture + Send + Sync> {
+            Arc::new(SolidColor::new(Color::new(x, x, x)))
+        };
+        Self {
+            base_color,
+            metallic: grey(0.0),
+            roughness: grey(0.5),
+            specular: grey(0.5),
+            sheen: grey(0.0),
+            clearcoat: grey(0.0),
+            clearcoat_roughness: 0.1,
+            transmission: grey(0.0),
+            index: 1.5,
+            emission: None,
+        }
+    }
+
+    fn params(&self, rec: &HitRecord) -> Params {
+        let scalar = |t: &Arc<dyn Texture + Send + Sync>| t.value(rec.u, rec.v, &rec.p).x.clamp(0.0, 1.0);
+        let roughness = scalar(&self.roughness);
+        Params {
+            base_color: self.base_color.value(rec.u, rec.v, &rec.p),
+            metallic: scalar(&self.metallic),
+            roughness: roughness.max(MIN_ROUGHNESS),
+            glass_roughness: roughness,
+            specular: scalar(&self.specular),
+            sheen: scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            transmission: scalar(&self.transmission),
+        }
+    }
+
+    fn clearcoat_distribution(&self) -> TrowbridgeReitz {
+        glass::distribution(self.clearcoat_roughness.max(MIN_ROUGHNESS))
+    }
+
+    fn lobe_weights(&self, wo: &Vec3, p: &Params) -> LobeWeights {
+        let dielectric = 1.0 - p.metallic;
+        let diffuse = dielectric * (1.0 - p.transmission) * (average(&p.base_color) + p.sheen);
+        let specular = (1.0 - dielectric * p.transmission) * average(&specular_fresnel(wo.z, p));
+        let transmission = dielectric * p.transmission;
+        let clearcoat = 0.25 * p.clearcoat * schlick(0.04, wo.z);
+        let total = diffuse + specular + transmission + clearcoat;
+        if total <= 0.0 {
+            return LobeWeights {diffuse: 1.0, specular: 0.0, transmission: 0.0, clearcoat: 0.0};
+        }
+        LobeWeights {
+            diffuse: diffuse / total,
+            specular: specular / total,
+            transmission: transmission / total,
+            clearcoat: clearcoat / total,
+        }
+    }
+
+    /// Sum of the lobes and of their densities for local directions.
+    fn eval_pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> (Color, f32) {
+        let black = Color::new(0.0, 0.0, 0.0);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return (black, 0.0);
+        }
+        let p = self.params(rec);
+        let weights = self.lobe_weights(wo, &p);
+        let distrib = glass::distribution(p.roughness);
+        let dielectric = 1.0 - p.metallic;
+        let mut f = black;
+        let mut pdf = 0.0;
+
+        // Rough glass, smooth glass is only reached by sampling
+        let glass = glass::distribution(p.glass_roughness);
+        if weights.transmission > 0.0 && !glass.is_smooth() {
+            let (ft, pdf_t) = glass::rough_eval_pdf(wo, wi, glass::relative_index(self.index, rec), &glass);
+            f = f + glass_tint(wi, &p) * (ft * dielectric * p.transmission);
+            pdf += weights.transmission * pdf_t;
+        }
+        if wi.z < 0.0 {
+            return (f, pdf);
+        }
+
+        // Burley's diffuse with retro-reflection, plus sheen
+        let wm = (*wo + *wi).normalize();
+        let cos_d = wi.dot(&wm);
+        let fd90 = 0.5 + 2.0 * p.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * pow5(1.0 - wo.z)) * (1.0 + (fd90 - 1.0) * pow5(1.0 - wi.z));
+        let sheen = p.sheen * pow5(1.0 - cos_d);
+        let diffuse = (p.base_color * (fd / PI) + Color::new(sheen, sheen, sheen))
+            * (dielectric * (1.0 - p.transmission));
+        f = f + diffuse;
+        pdf += weights.diffuse * sampling::cosine_hemisphere_pdf(wi.z);
+
+        // Specular reflection, dielectric or metallic
+        let g_d = distrib.d(&wm) * distrib.g(wo, wi) / (4.0 * wo.z * wi.z);
+        f = f + specular_fresnel(cos_d, &p) * (g_d * (1.0 - dielectric * p.transmission));
+        pdf += weights.specular * distrib.pdf(wo, &wm) / (4.0 * wo.dot(&wm));
+
+        // Clear coat on top
+        if p.clearcoat > 0.0 {
+            let coat = self.clearcoat_distribution();
+            let fc = 0.25 * p.clearcoat * schlick(0.04, cos_d);
+            let value = coat.d(&wm) * coat.g(wo, wi) * fc / (4.0 * wo.z * wi.z);
+            f = f + Color::new(value, value, value);
+            pdf += weights.clearcoat * coat.pdf(wo, &wm) / (4.0 * wo.dot(&wm));
+        }
+        (f, pdf)
+    }
+}
+
+/// Glass reflects white and refracts the square root of the base colour, so
+/// that going in and out of an object tints it once.
+fn glass_tint(wi: &Vec3, p: &Params) -> Color {
+    if wi.z > 0.0 {
+        Color::new(1.0, 1.0, 1.0)
+    } else {
+        Color::new(p.base_color.x.sqrt(), p.base_color.y.sqrt(), p.base_color.z.sqrt())
+    }
+}
+
+/// Schlick's Fresnel with the metallic blend of the normal incidence colour.
+fn specular_fresnel(cos_theta: f32, p: &Params) -> Color {
+    let dielectric = 0.08 * p.specular;
+    let f0 = Color::new(dielectric, dielectric, dielectric) * (1.0 - p.metallic) + p.base_color * p.metallic;
+    let t = pow5(1.0 - cos_theta.clamp(0.0, 1.0));
+    f0 * (1.0 - t) + Color::new(t, t, t)
+}
+
+fn schlick(f0: f32, cos_theta: f32) -> f32 {
+    f0 + (1.0 - f0) * pow5(1.0 - cos_theta.clamp(0.0, 1.0))
+}
+
+fn pow5(x: f32) -> f32 {
+    let x2 = x * x;
+    x2 * x2 * x
+}
+
+fn average(c: &Color) -> f32 {
+    (c.x + c.y + c.z) / 3.0
+}
+
+impl Material for Principled {
+    fn eval(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color {
+        let frame = rec.shading_frame();
+        self.eval_pdf(&frame.to_local(*wo), &frame.to_local(*wi), rec).0
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32 {
+        let frame = rec.shading_frame();
+        self.eval_pdf(&frame.to_local(*wo), &frame.to_local(*wi), rec).1
+    }
+
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, uc: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        let frame = rec.shading_frame();
+        let wo_local = frame.to_local(*wo);
+        if wo_local.z <= 0.0 {
+            return None;
+        }
+        let p = self.params(rec);
+        let weights = self.lobe_weights(&wo_local, &p);
+        let distrib = glass::distribution(p.roughness);
+        let reflect = |wm: Vec3| {
+            let wi = wm * (2.0 * wo_local.dot(&wm)) - wo_local;
+            if wi.z > 0.0 { Some(wi) } else { None }
+        };
+
+        // Pick a lobe with `uc`, what is left of it chooses between
+        // reflection and refraction for the glass
+        let specular_end = weights.diffuse + weights.specular;
+        let clearcoat_end = specular_end + weights.clearcoat;
+        let wi = if uc < weights.diffuse {
+            sampling::cosine_hemisphere(u)
+        } else if uc < specular_end {
+            reflect(distrib.sample_wm(&wo_local, u))?
+        } else if uc < clearcoat_end {
+            reflect(self.clearcoat_distribution().sample_wm(&wo_local, u))?
+        } else {
+            let uc = (uc - clearcoat_end) / weights.transmission;
+            let eta = glass::relative_index(self.index, rec);
+            let glass = glass::distribution(p.glass_roughness);
+            let wm = if glass.is_smooth() { Vec3::new(0.0, 0.0, 1.0) } else { glass.sample_wm(&wo_local, u) };
+            let fresnel = fresnel_dielectric(wo_local.dot(&wm), eta);
+            let wi = if uc < fresnel {
+                reflect(wm)?
+            } else {
+                refract(&wo_local, &wm, eta).filter(|wi| wi.z < 0.0)?
+            };
+            if glass.is_smooth() {
+                let chosen = if wi.z > 0.0 { fresnel } else { 1.0 - fresnel };
+                let f = glass_tint(&wi, &p) * ((1.0 - p.metallic) * p.transmission * chosen / wi.z.abs());
+                let pdf = weights.transmission * chosen;
+                return Some(BsdfSample {wi: frame.to_world(wi), f, pdf, specular: true});
+            }
+            wi
+        };
+
+        let (f, pdf) = self.eval_pdf(&wo_local, &wi, rec);
+        if pdf <= 0.0 || f.near_zero() {
+            return None;
+        }
+        Some(BsdfSample {wi: frame.to_world(wi), f, pdf, specular: false})
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        match &self.emission {
+            Some(emission) if rec.front => emission.value(rec.u, rec.v, &rec.p),
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emission.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::check_bsdf;
+
+    fn grey(x: f32) -> Arc<SolidColor> {
+        Arc::new(SolidColor::new(Color::new(x, x, x)))
+    }
+
+    #[test]
+    fn glass_is_tinted_once_per_object() {
+        let mat: Arc<dyn Material> = Arc::new(Principled {
+            transmission: grey(1.0),
+            ..Principled::new(Color::new(0.25, 1.0, 1.0))
+        });
+        let wo = Vec3::new(0.3, 0.0, 1.0).normalize();
+        let wi = Vec3::new(-0.2, 0.0, -1.0).normalize();
+        for front in [true, false] {
+            let mut rec = HitRecord::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, mat.clone());
+            rec.front = front;
+            let f = mat.eval(&wo, &wi, &rec);
+            assert!(f.y > 0.0);
+            assert!((f.x / f.y - 0.5).abs() < 1e-4, "red / green = {}", f.x / f.y);
+        }
+    }
+
+    #[test]
+    fn plastic_is_consistent() {
+        check_bsdf(Arc::new(Principled::new(Color::new(0.8, 0.3, 0.2))));
+    }
+
+    #[test]
+    fn every_lobe_is_consistent() {
+        check_bsdf(Arc::new(Principled {
+            metallic: grey(0.3),
+            roughness: grey(0.6),
+            sheen: grey(0.5),
+            clearcoat: grey(1.0),
+            transmission: grey(0.5),
+            ..Principled::new(Color::new(0.6, 0.6, 0.6))
+        }));
+    }
+
+    #[test]
+    fn glass_is_consistent() {
+        check_bsdf(Arc::new(Principled {
+            roughness: grey(0.2),
+            transmission: grey(1.0),
+            ..Principled::new(Color::new(1.0, 1.0, 1.0))
+        }));
+    }
+
+    #[test]
+    fn smooth_glass_refracts_as_a_delta() {
+        let mat: Arc<dyn Material> = Arc::new(Principled {
+            roughness: grey(0.0),
+            transmission: grey(1.0),
+            ..Principled::new(Color::new(1.0, 1.0, 1.0))
+        });
+        let rec = HitRecord::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, mat.clone());
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let mut refracted = 0;
+        for i in 0..100 {
+            let uc = (i as f32 + 0.5) / 100.0;
+            let Some(s) = mat.sample(&wo, &rec, uc, [0.3, 0.7]) else { continue };
+            // White glass with nothing else loses no energy
+            let weight = s.weight(&rec.n);
+            assert!((weight - Color::new(1.0, 1.0, 1.0)).length() < 1e-4, "weight {:?}", weight);
+            if s.wi.z < 0.0 {
+                // Snell's law with index 1.5
+                assert!(s.specular);
+                assert!((s.wi.x - (-0.6 / 1.5)).abs() < 1e-4, "refracted to {:?}", s.wi);
+                refracted += 1;
+            }
+        }
+        assert!(refracted > 0);
+        assert_eq!(mat.pdf(&wo, &Vec3::new(-0.4, 0.0, -0.9165), &rec), 0.0);
+    }
+
+    #[test]
+    fn metal_is_consistent() {
+        check_bsdf(Arc::new(Principled {
+            metallic: grey(1.0),
+            roughness: grey(0.3),
+            ..Principled::new(Color::new(0.9, 0.6, 0.3))
+        }));
+    }
+}
@@ -1,6 +1,9 @@
-use crate::utilities::clamp;
+use std::sync::Arc;
+
 use crate::vector::*;
-use crate::objects::Scene;
+use crate::objects::{HitRecord, Material, ObjectId, Scene};
+use crate::lights::Light;
+use crate::sampler::Sampler;
 
 pub struct Ray {
     pub origin: Point3,
@@ -28,47 +31,331 @@ pub fn refract(uv: &Vec3, n: &Vec3, relative_index: f32) -> Vec3 {
     r_out_perp + r_out_par
 }
 
-pub fn postprocess_color(pixel_color: Color, samples: usize) -> [i32; 3] {
-    // Divide by the number of samples and gamma-correct
-    let scale = 1.0 / samples as f32;
-    let r = (pixel_color.x * scale).sqrt();
-    let g = (pixel_color.y * scale).sqrt();
-    let b = (pixel_color.z * scale).sqrt();
-
-    // Return the scaled color
-    return [
-        (256.0 * clamp(r, 0.0, 0.999)) as i32,
-        (256.0 * clamp(g, 0.0, 0.999)) as i32,
-        (256.0 * clamp(b, 0.0, 0.999)) as i32,
-    ];
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
 }
 
-pub fn ray_color(r: Ray, world: &Scene, depth: usize) -> Color {
-    // Do not go over depth with children
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
-
-    // Look for a hit otherwise
-    match world.hit(&r, 0.001, 100.0) {
-
-        // Hit found
-        Some(rec) => {
-            let mut attenuation = Color::new(0.0, 0.0, 0.0);
-            match rec.mat.scatter(&r, &rec, &mut attenuation) {
-                Some(sr) => {
-                    return ray_color(sr, world, depth - 1) * attenuation;
-                },
-                None => {
-                    return attenuation;
-                },
+/// Path traced radiance along `r`. Every bounce adds the delta lights, and
+/// samples a direction towards the emitters or the environment and another
+/// one from the BSDF, combining both with multiple importance sampling.
+/// The path keeps the stack of objects it went into, so that segments are
+/// attenuated by the medium they cross whatever they end on.
+pub fn ray_color(r: Ray, world: &Scene, max_depth: usize, sampler: &mut dyn Sampler) -> Color {
+    let mut radiance = Color::new(0.0, 0.0, 0.0);
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = r;
+    // Camera rays and specular bounces cannot be light sampled
+    let mut specular = true;
+    let mut bsdf_pdf = 0.0;
+    let mut media: Vec<Arc<dyn Material>> = vec![];
+
+    for _ in 0..max_depth {
+        let mut rec = match world.hit(&ray, 0.001, 100.0) {
+            Some(rec) => rec,
+            None => {
+                let weight = if specular {
+                    1.0
+                } else {
+                    power_heuristic(bsdf_pdf, world.light_pdf(&ray.origin, &ray.direction, None))
+                };
+                radiance += &(throughput * world.background.color(&ray) * weight);
+                break;
+            },
+        };
+
+        if let Some(medium) = media.last() {
+            throughput = throughput * medium.transmittance(rec.t * ray.direction.length());
+        }
+
+        // Emission found by the BSDF sample, weighted against light sampling
+        let emitted = rec.mat.emitted(&ray, &rec);
+        if !emitted.near_zero() {
+            let weight = if specular {
+                1.0
+            } else {
+                power_heuristic(bsdf_pdf, world.light_pdf(&ray.origin, &ray.direction, Some(&rec)))
+            };
+            radiance += &(throughput * emitted * weight);
+        }
+
+        // Shadow rays towards every delta light and a sampled light
+        let n = rec.n;
+        rec.perturb_normal();
+        let wo = -ray.direction.normalize();
+        let medium = media.last().map(|m| m.as_ref());
+        for light in world.lights.iter() {
+            radiance += &(throughput * delta_light(world, &rec, &wo, light.as_ref(), medium));
+        }
+        if world.has_sampled_lights() {
+            let uc = sampler.get_1d();
+            let u = sampler.get_2d();
+            if let Some((wi, light)) = world.sample_light(&rec.p, uc, u) {
+                radiance += &(throughput * direct_light(world, &rec, &wo, &wi, light, medium));
             }
-        },
+        }
+
+        // Continue along a direction picked by the BSDF
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let bs = match rec.mat.sample(&wo, &rec, uc, u) {
+            Some(bs) => bs,
+            None => break,
+        };
+        throughput = throughput * bs.weight(&rec.n);
+
+        // Going through the surface of a volume enters it or leaves it
+        if bs.wi.dot(&n) < 0.0 && rec.mat.has_interior() {
+            if rec.front {
+                media.push(Arc::clone(&rec.mat));
+            } else if let Some(i) = media.iter().rposition(|m| Arc::ptr_eq(m, &rec.mat)) {
+                media.remove(i);
+            }
+        }
+        specular = bs.specular;
+        bsdf_pdf = bs.pdf;
+        ray = Ray::new(rec.p, bs.wi);
+    }
+
+    radiance
+}
+
+/// Attenuation along `distance` through `medium`, if the shadow ray starts
+/// inside one.
+fn shadow_transmittance(medium: Option<&dyn Material>, distance: f32) -> Color {
+    match medium {
+        Some(medium) if distance.is_finite() => medium.transmittance(distance),
+        _ => Color::new(1.0, 1.0, 1.0),
+    }
+}
+
+/// Light from a delta light scattered towards `wo`, zero if occluded.
+fn delta_light(world: &Scene, rec: &HitRecord, wo: &Vec3, light: &dyn Light, medium: Option<&dyn Material>) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let ls = match light.sample_li(&rec.p) {
+        Some(ls) => ls,
+        None => return black,
+    };
+    let f = rec.mat.eval(wo, &ls.wi, rec);
+    if f.near_zero() {
+        return black;
+    }
+    let shadow = Ray::new(rec.p, ls.wi);
+    if world.hit(&shadow, 0.001, ls.distance * (1.0 - 1e-4)).is_some() {
+        return black;
+    }
+    f * ls.li * shadow_transmittance(medium, ls.distance) * ls.wi.dot(&rec.n).abs()
+}
 
-        // Shadow rays should go here
-        None => {
-            let t = 0.5 * (r.direction.y + 1.0);
-            return Color::new(1.0, 1.0, 1.0) * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t;
+/// Light arriving from `wi` and scattered towards `wo`, for a direction
+/// picked by light sampling towards `light`, the background if `None`.
+fn direct_light(
+    world: &Scene,
+    rec: &HitRecord,
+    wo: &Vec3,
+    wi: &Vec3,
+    light: Option<ObjectId>,
+    medium: Option<&dyn Material>,
+) -> Color {
+    let black = Color::new(0.0, 0.0, 0.0);
+    let f = rec.mat.eval(wo, wi, rec);
+    if f.near_zero() {
+        return black;
+    }
+
+    // Zero unless the shadow ray reaches the sampled light first
+    let shadow = Ray::new(rec.p, *wi);
+    let light_rec = world.hit(&shadow, 0.001, 100.0);
+    let reached = match (&light_rec, light) {
+        (Some(light_rec), Some(id)) => light_rec.object == Some(id),
+        (None, None) => true,
+        _ => false,
+    };
+    if !reached {
+        return black;
+    }
+    let light_pdf = world.light_pdf(&rec.p, wi, light_rec.as_ref());
+    if light_pdf <= 0.0 {
+        return black;
+    }
+    let emitted = match &light_rec {
+        Some(light_rec) => {
+            light_rec.mat.emitted(&shadow, light_rec) * shadow_transmittance(medium, light_rec.t * wi.length())
         },
+        None => world.background.color(&shadow),
     };
+    let weight = power_heuristic(light_pdf, rec.mat.pdf(wo, wi, rec));
+    f * emitted * (wi.dot(&rec.n).abs() * weight / light_pdf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Background;
+    use crate::glass::ThinDielectric;
+    use crate::lights::PointLight;
+    use crate::objects::{Dielectric, DiffuseLight, Hittable, Lambertian, Sphere};
+    use crate::sampler::IndependentSampler;
+    use crate::sampling::{uniform_sphere, uniform_sphere_pdf};
+    use crate::utilities::PI;
+
+    /// Glass keeping half of the red every half unit.
+    fn tinted_glass() -> Dielectric {
+        Dielectric::tinted(1.5, Color::new(0.5, 1.0, 1.0), 0.5)
+    }
+
+    /// Red over green of the light seen through a glass ball of radius 1 with
+    /// a lamp of radius 0.5 inside, and `inside` in between.
+    fn red_over_green(inside: Vec<Arc<dyn Hittable + Send + Sync>>) -> f32 {
+        let mut objects: Vec<Arc<dyn Hittable + Send + Sync>> = vec![
+            Arc::new(Sphere {centre: Point3::new(0.0, 0.0, 0.0), radius: 1.0, mat: Arc::new(tinted_glass())}),
+            Arc::new(Sphere {
+                centre: Point3::new(0.0, 0.0, 0.0),
+                radius: 0.5,
+                mat: Arc::new(DiffuseLight::new(Color::new(1.0, 1.0, 1.0))),
+            }),
+        ];
+        objects.extend(inside);
+        let mut world = Scene::new(objects);
+        world.set_background(Background::Solid(Color::new(0.0, 0.0, 0.0)));
+        world.build_bvh();
+
+        let mut sampler = IndependentSampler::new(1);
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..1000 {
+            let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+            sum += &ray_color(r, &world, 10, &mut sampler);
+        }
+        assert!(sum.y > 0.0);
+        sum.x / sum.y
+    }
+
+    #[test]
+    fn light_inside_tinted_glass_is_attenuated() {
+        // The ray crosses half a unit of glass
+        let ratio = red_over_green(vec![]);
+        assert!((ratio - 0.5).abs() < 0.01, "red / green = {}", ratio);
+    }
+
+    #[test]
+    fn thin_glass_inside_tinted_glass_is_not_a_medium() {
+        let bubble = Sphere {
+            centre: Point3::new(0.0, 0.0, 0.0),
+            radius: 0.75,
+            mat: Arc::new(ThinDielectric::new(1.5, Color::new(1.0, 1.0, 1.0))),
+        };
+        let ratio = red_over_green(vec![Arc::new(bubble)]);
+        assert!((ratio - 0.5).abs() < 0.01, "red / green = {}", ratio);
+    }
+
+    #[test]
+    fn shadow_rays_are_attenuated_by_the_medium() {
+        let diffuse: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let rec = HitRecord::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, diffuse);
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        let glass = tinted_glass();
+        let expected = glass.transmittance(1.0);
+
+        let light = PointLight::new(Point3::new(0.0, 0.0, 1.0), Color::new(1.0, 1.0, 1.0));
+        let world = Scene::new(vec![]);
+        let clear = delta_light(&world, &rec, &wo, &light, None);
+        let absorbed = delta_light(&world, &rec, &wo, &light, Some(&glass));
+        assert!((absorbed.x / clear.x - expected.x).abs() < 1e-4);
+        assert!((absorbed.y / clear.y - expected.y).abs() < 1e-4);
+
+        let lamp = Arc::new(Sphere {
+            centre: Point3::new(0.0, 0.0, 1.5),
+            radius: 0.5,
+            mat: Arc::new(DiffuseLight::new(Color::new(1.0, 1.0, 1.0))),
+        });
+        let id = ObjectId::of(lamp.as_ref());
+        let mut world = Scene::new(vec![lamp]);
+        world.build_bvh();
+        let clear = direct_light(&world, &rec, &wo, &wo, Some(id), None);
+        let absorbed = direct_light(&world, &rec, &wo, &wo, Some(id), Some(&glass));
+        assert!(clear.y > 0.0);
+        assert!((absorbed.x / clear.x - expected.x).abs() < 1e-4);
+        assert!((absorbed.y / clear.y - expected.y).abs() < 1e-4);
+    }
+
+    const LAMP_CENTRE: Point3 = Point3 {x: 0.5, y: 1.0, z: 0.0};
+    const LAMP_RADIUS: f32 = 0.3;
+
+    /// Small lamp over a diffuse floor, with the lamp registered for light
+    /// sampling only if `light_sampling` is set.
+    fn lamp_over_floor(light_sampling: bool) -> Scene {
+        let mut world = Scene::new(vec![
+            Arc::new(Sphere {
+                centre: Point3::new(0.0, -1000.0, 0.0),
+                radius: 1000.0,
+                mat: Arc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7))),
+            }),
+            Arc::new(Sphere {
+                centre: LAMP_CENTRE,
+                radius: LAMP_RADIUS,
+                mat: Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+            }),
+        ]);
+        world.set_background(Background::Solid(Color::new(0.0, 0.0, 0.0)));
+        if light_sampling {
+            world.build_bvh();
+        }
+        world
+    }
+
+    /// Mean and standard error of the green radiance seen on the floor.
+    fn floor_radiance(world: &Scene, seed: u64) -> (f64, f64) {
+        let mut sampler = IndependentSampler::new(seed);
+        let n = 200_000;
+        let (mut sum, mut sum_squares) = (0.0, 0.0);
+        for _ in 0..n {
+            let r = Ray::new(Point3::new(0.0, 1.0, 3.0), Vec3::new(0.0, -1.0, -3.0));
+            let y = ray_color(r, world, 3, &mut sampler).y as f64;
+            sum += y;
+            sum_squares += y * y;
+        }
+        let mean = sum / n as f64;
+        (mean, f64::sqrt((sum_squares / n as f64 - mean * mean) / n as f64))
+    }
+
+    #[test]
+    fn light_sampling_agrees_with_bsdf_sampling() {
+        let (mis, mis_error) = floor_radiance(&lamp_over_floor(true), 1);
+        let (bsdf, bsdf_error) = floor_radiance(&lamp_over_floor(false), 2);
+        let error = f64::hypot(mis_error, bsdf_error);
+        assert!(mis > 0.0);
+        assert!((mis - bsdf).abs() < 5.0 * error, "MIS {} against BSDF sampling {} +- {}", mis, bsdf, error);
+        // Light sampling is what makes small lights converge
+        assert!(mis_error < 0.5 * bsdf_error);
+    }
+
+    #[test]
+    fn light_pdf_is_the_density_of_sample_light() {
+        let world = lamp_over_floor(true);
+        let origin = Point3::new(0.0, 0.001, 0.0);
+        let mut sampler = IndependentSampler::new(3);
+        for _ in 0..1000 {
+            let (wi, light) = world.sample_light(&origin, sampler.get_1d(), sampler.get_2d()).unwrap();
+            let rec = world.hit(&Ray::new(origin, wi), 0.001, 100.0).unwrap();
+            assert_eq!(rec.object, light);
+            // Uniform over the cone the lamp subtends, the only light
+            let sin_theta_max = LAMP_RADIUS / (LAMP_CENTRE - origin).length();
+            let cone = 2.0 * PI * (1.0 - f32::sqrt(1.0 - sin_theta_max * sin_theta_max));
+            let pdf = world.light_pdf(&origin, &wi, Some(&rec));
+            assert!((pdf * cone - 1.0).abs() < 1e-3, "pdf {} for {}", pdf, 1.0 / cone);
+        }
+
+        // Densities integrate to one over the directions that reach the lamp
+        let n = 400_000;
+        let integral: f32 = (0..n)
+            .map(|_| {
+                let wi = uniform_sphere(sampler.get_2d());
+                let rec = world.hit(&Ray::new(origin, wi), 0.001, 100.0);
+                world.light_pdf(&origin, &wi, rec.as_ref()) / uniform_sphere_pdf()
+            })
+            .sum::<f32>() / n as f32;
+        assert!((integral - 1.0).abs() < 0.03, "light pdf integrates to {}", integral);
+    }
+}
+
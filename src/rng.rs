@@ -0,0 +1,62 @@
+use rand::{Error, RngCore};
+
+/// Small SplitMix64 generator. Every camera sample gets its own stream
+/// derived from the seed and its pixel and sample indices, so images do not
+/// depend on how the work is split between threads.
+#[derive(Clone, Debug)]
+pub struct SampleRng {
+    state: u64,
+}
+
+impl SampleRng {
+    pub fn seeded(seed: u64) -> Self {
+        Self {state: mix(seed)}
+    }
+
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> Self {
+        Self {state: mix(mix(mix(seed) ^ pixel) ^ sample)}
+    }
+}
+
+/// Uniform value in [0, 1) hashed from `seed` and the bits of `values`, for
+/// random choices that must come out the same every time for a given hit.
+/// Choices that must not be correlated use different seeds.
+pub fn hash_unit(seed: u64, values: &[f32]) -> f32 {
+    let h = values.iter().fold(mix(seed), |h, v| mix(h ^ v.to_bits() as u64));
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let z = mix(self.state);
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        fill(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        fill(self, dest);
+        Ok(())
+    }
+}
+
+fn fill(rng: &mut SampleRng, dest: &mut [u8]) {
+    for chunk in dest.chunks_mut(8) {
+        let bytes = rng.next_u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
@@ -0,0 +1,443 @@
+use clap::ValueEnum;
+use rand::Rng;
+
+use crate::rng::SampleRng;
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Source of sample values for one camera sample at a time. The first 2D
+/// value is the position in the pixel, the second one the lens position, and
+/// the rest are consumed by each bounce in order.
+pub trait Sampler {
+    /// Restarts the dimensions for sample `sample` of the pixel with linear
+    /// index `pixel`.
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> [f32; 2];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SamplerKind {
+    /// Uniform random values
+    Independent,
+    /// Jittered strata, shuffled per pixel and dimension
+    Stratified,
+    /// Owen-scrambled Halton sequence
+    Halton,
+    /// Owen-scrambled Sobol points, padded per dimension
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn create(&self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(samples_per_pixel, seed)),
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: SampleRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {seed, rng: SampleRng::seeded(seed)}
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64) {
+        self.rng = SampleRng::for_sample(self.seed, pixel, sample);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> [f32; 2] {
+        [self.rng.gen(), self.rng.gen()]
+    }
+}
+
+pub struct StratifiedSampler {
+    samples: u64,
+    x_strata: u64,
+    y_strata: u64,
+    seed: u64,
+    pixel: u64,
+    sample: u64,
+    dimension: u64,
+    rng: SampleRng,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> Self {
+        // The most square grid with exactly one sample per stratum
+        let samples = samples_per_pixel.max(1) as u64;
+        let x_strata = (1..=samples)
+            .take_while(|x| x * x <= samples)
+            .filter(|x| samples.is_multiple_of(*x))
+            .last()
+            .unwrap_or(1);
+        Self {
+            samples,
+            x_strata,
+            y_strata: samples / x_strata,
+            seed,
+            pixel: 0,
+            sample: 0,
+            dimension: 0,
+            rng: SampleRng::seeded(seed),
+        }
+    }
+
+    fn stratum(&self) -> u64 {
+        let hash = hash(&[self.seed, self.pixel, self.dimension]);
+        permutation_element(self.sample, self.samples, hash)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64) {
+        self.pixel = pixel;
+        self.sample = sample;
+        self.dimension = 0;
+        self.rng = SampleRng::for_sample(self.seed, pixel, sample);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.stratum();
+        self.dimension += 1;
+        f32::min((stratum as f32 + self.rng.gen::<f32>()) / self.samples as f32, ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> [f32; 2] {
+        let stratum = self.stratum();
+        self.dimension += 2;
+        let x = stratum % self.x_strata;
+        let y = stratum / self.x_strata;
+        [
+            f32::min((x as f32 + self.rng.gen::<f32>()) / self.x_strata as f32, ONE_MINUS_EPSILON),
+            f32::min((y as f32 + self.rng.gen::<f32>()) / self.y_strata as f32, ONE_MINUS_EPSILON),
+        ]
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Every pixel walks its own scrambling of the same Halton points, past the
+/// table of primes the dimensions wrap around with different scrambles.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    sample: u64,
+    dimension: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {seed, pixel: 0, sample: 0, dimension: 0}
+    }
+
+    fn sample_dimension(&mut self) -> f32 {
+        let dim = self.dimension;
+        self.dimension += 1;
+        let base = PRIMES[(dim % PRIMES.len() as u64) as usize];
+        owen_scrambled_radical_inverse(base, self.sample, hash(&[self.seed, self.pixel, dim]))
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64) {
+        self.pixel = pixel;
+        self.sample = sample;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.sample_dimension()
+    }
+
+    fn get_2d(&mut self) -> [f32; 2] {
+        [self.sample_dimension(), self.sample_dimension()]
+    }
+}
+
+/// Padded Sobol: every request takes the first one or two Sobol dimensions,
+/// with the sample order shuffled and the points Owen-scrambled per pixel and
+/// dimension. Only needs the first two generator matrices.
+pub struct SobolSampler {
+    samples: u64,
+    seed: u64,
+    pixel: u64,
+    sample: u64,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> Self {
+        Self {samples: samples_per_pixel.max(1) as u64, seed, pixel: 0, sample: 0, dimension: 0}
+    }
+
+    fn index_and_hash(&mut self) -> (u32, u64) {
+        let hash = hash(&[self.seed, self.pixel, self.dimension]);
+        self.dimension += 1;
+        let index = permutation_element(self.sample, self.samples, hash);
+        (index as u32, hash)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, sample: u64) {
+        self.pixel = pixel;
+        self.sample = sample;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (index, hash) = self.index_and_hash();
+        to_unit_float(fast_owen_scramble(sobol_dim0(index), hash as u32))
+    }
+
+    fn get_2d(&mut self) -> [f32; 2] {
+        let (index, hash) = self.index_and_hash();
+        [
+            to_unit_float(fast_owen_scramble(sobol_dim0(index), hash as u32)),
+            to_unit_float(fast_owen_scramble(sobol_dim1(index), (hash >> 32) as u32)),
+        ]
+    }
+}
+
+fn to_unit_float(v: u32) -> f32 {
+    f32::min(v as f32 * f32::powi(2.0, -32), ONE_MINUS_EPSILON)
+}
+
+fn sobol_dim0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_dim1(index: u32) -> u32 {
+    // Generator matrix of the second dimension, v[k] = v[k-1] ^ (v[k-1] >> 1)
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 == 1 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// Laine and Karras' hash-based Owen scrambling, as in pbrt-v4.
+fn fast_owen_scramble(v: u32, seed: u32) -> u32 {
+    let mut v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+    v.reverse_bits()
+}
+
+fn owen_scrambled_radical_inverse(base: u64, index: u64, hash: u64) -> f32 {
+    // Permute every digit with a hash of the digits below it
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed = 0u64;
+    let mut a = index;
+    while 1.0 - (base - 1) as f32 * (inv_base_m as f32) < 1.0 {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_hash = mix_bits(hash ^ reversed);
+        let digit = permutation_element(digit, base, digit_hash);
+        reversed = reversed * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    f32::min((reversed as f64 * inv_base_m) as f32, ONE_MINUS_EPSILON)
+}
+
+fn mix_bits(v: u64) -> u64 {
+    let mut v = v;
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x51_7cc1_b727_220a, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+/// Element `i` of a random permutation of `0..n` chosen by `seed`, from
+/// Kensler's "Correlated Multi-Jittered Sampling".
+fn permutation_element(i: u64, n: u64, seed: u64) -> u64 {
+    let n = n as u32;
+    let seed = seed as u32;
+    let mut i = i as u32;
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    ((i.wrapping_add(seed)) % n) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The 2D samples of every sample of `pixel`, taken as the `dimension`-th
+    /// request after the start.
+    fn points(sampler: &mut dyn Sampler, samples: u64, pixel: u64, dimension: usize) -> Vec<[f32; 2]> {
+        (0..samples).map(|s| {
+            sampler.start_pixel_sample(pixel, s);
+            for _ in 0..dimension {
+                sampler.get_2d();
+            }
+            sampler.get_2d()
+        }).collect()
+    }
+
+    /// Whether every box of `2^-a` by `2^-(m - a)` holds exactly one of the
+    /// `2^m` points, for every split of `m`.
+    fn is_binary_net(points: &[[f32; 2]]) -> bool {
+        let m = points.len().trailing_zeros();
+        assert_eq!(points.len(), 1 << m);
+        (0..=m).all(|a| {
+            let mut counts = vec![0; points.len()];
+            for p in points.iter() {
+                let x = (p[0] * (1u32 << a) as f32) as usize;
+                let y = (p[1] * (1u32 << (m - a)) as f32) as usize;
+                counts[(x << (m - a)) + y] += 1;
+            }
+            counts.iter().all(|&c| c == 1)
+        })
+    }
+
+    #[test]
+    fn permutation_element_is_a_permutation() {
+        for n in [1, 2, 7, 16, 100] {
+            let mut seen: Vec<u64> = (0..n).map(|i| permutation_element(i, n, 0x1234_5678)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..n).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn stratified_covers_each_stratum_once() {
+        for (samples, x_strata, y_strata) in [(16, 4, 4), (12, 3, 4), (7, 1, 7)] {
+            let mut sampler = StratifiedSampler::new(samples, 3);
+            for dimension in 0..3 {
+                let mut counts = vec![0; samples];
+                for p in points(&mut sampler, samples as u64, 42, dimension) {
+                    let x = (p[0] * x_strata as f32) as usize;
+                    let y = (p[1] * y_strata as f32) as usize;
+                    counts[y * x_strata + x] += 1;
+                }
+                assert!(counts.iter().all(|&c| c == 1), "{} samples: {:?}", samples, counts);
+            }
+
+            // 1D requests are stratified over all the samples
+            let mut counts = vec![0; samples];
+            for s in 0..samples as u64 {
+                sampler.start_pixel_sample(42, s);
+                counts[(sampler.get_1d() * samples as f32) as usize] += 1;
+            }
+            assert!(counts.iter().all(|&c| c == 1));
+        }
+    }
+
+    #[test]
+    fn owen_scrambled_sobol_is_a_binary_net() {
+        // Padded per dimension, so every request is the same (0, 2)-sequence
+        let mut sampler = SobolSampler::new(64, 5);
+        for pixel in [0, 1, 1000] {
+            for dimension in 0..4 {
+                assert!(is_binary_net(&points(&mut sampler, 64, pixel, dimension)));
+            }
+        }
+
+        // Every power of two prefix of the scrambled sequence is a net
+        for seed in [0u32, 1, 0xdead_beef] {
+            for m in 0..=10 {
+                let points: Vec<[f32; 2]> = (0..1u32 << m).map(|i| [
+                    to_unit_float(fast_owen_scramble(sobol_dim0(i), seed)),
+                    to_unit_float(fast_owen_scramble(sobol_dim1(i), seed.rotate_left(16))),
+                ]).collect();
+                assert!(is_binary_net(&points), "seed {:x}, 2^{} points", seed, m);
+            }
+        }
+    }
+
+    #[test]
+    fn owen_scrambled_halton_keeps_strata() {
+        // The first base^k points fall in distinct intervals of 1 / base^k
+        for (base, n) in [(2u64, 64u64), (3, 81), (5, 125)] {
+            let mut counts = vec![0; n as usize];
+            for i in 0..n {
+                let x = owen_scrambled_radical_inverse(base, i, 0xabcd);
+                counts[(x * n as f32) as usize] += 1;
+            }
+            assert!(counts.iter().all(|&c| c == 1), "base {}", base);
+        }
+    }
+
+    #[test]
+    fn same_seed_reproduces_samples() {
+        for kind in [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+            let draw = |seed: u64| {
+                let mut sampler = kind.create(16, seed);
+                let mut values = vec![];
+                for pixel in [0, 7, 12345] {
+                    for s in 0..16 {
+                        sampler.start_pixel_sample(pixel, s);
+                        values.push(sampler.get_1d());
+                        values.extend(sampler.get_2d());
+                        values.extend(sampler.get_2d());
+                    }
+                }
+                values
+            };
+            assert_eq!(draw(9), draw(9), "{:?}", kind);
+            assert_ne!(draw(9), draw(10), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Warps from uniform samples in [0, 1)^2 to common distributions. Local
+//! directions are around +z, use `Onb` to bring them to world space.
+
+use crate::utilities::PI;
+use crate::vector::Vec3;
+
+pub fn uniform_sphere(u: [f32; 2]) -> Vec3 {
+    let z = 1.0 - 2.0 * u[0];
+    let r = f32::sqrt(f32::max(0.0, 1.0 - z * z));
+    let phi = 2.0 * PI * u[1];
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_sphere_pdf() -> f32 {
+    1.0 / (4.0 * PI)
+}
+
+pub fn uniform_hemisphere(u: [f32; 2]) -> Vec3 {
+    let z = u[0];
+    let r = f32::sqrt(f32::max(0.0, 1.0 - z * z));
+    let phi = 2.0 * PI * u[1];
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_hemisphere_pdf() -> f32 {
+    1.0 / (2.0 * PI)
+}
+
+/// Malley's method, projects a concentric disk sample up to the hemisphere.
+pub fn cosine_hemisphere(u: [f32; 2]) -> Vec3 {
+    let d = concentric_disk(u);
+    let z = f32::sqrt(f32::max(0.0, 1.0 - d.x * d.x - d.y * d.y));
+    Vec3::new(d.x, d.y, z)
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    f32::max(0.0, cos_theta) / PI
+}
+
+/// Directions within `cos_theta_max` of +z, uniform in solid angle.
+pub fn uniform_cone(u: [f32; 2], cos_theta_max: f32) -> Vec3 {
+    let z = 1.0 - u[0] * (1.0 - cos_theta_max);
+    let r = f32::sqrt(f32::max(0.0, 1.0 - z * z));
+    let phi = 2.0 * PI * u[1];
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: f32) -> f32 {
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+/// Polar mapping of the unit disk, z is zero.
+pub fn uniform_disk(u: [f32; 2]) -> Vec3 {
+    let r = u[0].sqrt();
+    let theta = 2.0 * PI * u[1];
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+/// Shirley-Chiu mapping of the unit disk, keeps strata less distorted than
+/// `uniform_disk`.
+pub fn concentric_disk(u: [f32; 2]) -> Vec3 {
+    let ox = 2.0 * u[0] - 1.0;
+    let oy = 2.0 * u[1] - 1.0;
+    if ox == 0.0 && oy == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+
+    let (r, theta) = if ox.abs() > oy.abs() {
+        (ox, PI / 4.0 * (oy / ox))
+    } else {
+        (oy, PI / 2.0 - PI / 4.0 * (ox / oy))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+pub fn uniform_disk_pdf() -> f32 {
+    1.0 / PI
+}
+
+/// Barycentric coordinates of a point uniformly distributed over a triangle,
+/// the weights of its second and third vertices.
+pub fn uniform_triangle(u: [f32; 2]) -> [f32; 2] {
+    let su = u[0].sqrt();
+    [su * (1.0 - u[1]), su * u[1]]
+}
+
+/// Orthonormal basis with `w` along a given unit vector.
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(w: Vec3) -> Self {
+        // Duff et al., "Building an Orthonormal Basis, Revisited"
+        let sign = 1.0f32.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        let u = Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x);
+        let v = Vec3::new(b, sign + w.y * w.y * a, -w.y);
+        Self {u, v, w}
+    }
+
+    pub fn to_world(&self, local: Vec3) -> Vec3 {
+        self.u * local.x + self.v * local.y + self.w * local.z
+    }
+
+    pub fn to_local(&self, world: Vec3) -> Vec3 {
+        Vec3::new(world.dot(&self.u), world.dot(&self.v), world.dot(&self.w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use super::*;
+    use crate::rng::SampleRng;
+
+    const BINS: usize = 20;
+    const SAMPLES: usize = 200_000;
+
+    fn phi(v: Vec3) -> f32 {
+        (v.y.atan2(v.x) + PI) / (2.0 * PI)
+    }
+
+    /// Bins the warp in coordinates where it must be uniform and compares the
+    /// histogram to a chi-square bound, then checks the pdf by estimating the
+    /// measure of the domain as the mean of 1 / pdf.
+    fn check_warp(
+        sample: impl Fn([f32; 2]) -> Vec3,
+        uniform_coords: impl Fn(Vec3) -> [f32; 2],
+        pdf: impl Fn(Vec3) -> f32,
+        measure: f32,
+    ) {
+        let mut rng = SampleRng::seeded(1);
+        let mut histogram = vec![0usize; BINS * BINS];
+        let mut inv_pdf_sum = 0.0f64;
+        for _ in 0..SAMPLES {
+            let v = sample([rng.gen(), rng.gen()]);
+            let [s, t] = uniform_coords(v);
+            let bin = |x: f32| ((x * BINS as f32) as usize).min(BINS - 1);
+            histogram[bin(s) * BINS + bin(t)] += 1;
+            inv_pdf_sum += 1.0 / pdf(v) as f64;
+        }
+
+        // Mean plus six standard deviations of the chi-square distribution
+        let dof = (BINS * BINS - 1) as f64;
+        let bound = dof + 6.0 * (2.0 * dof).sqrt();
+        let expected = SAMPLES as f64 / (BINS * BINS) as f64;
+        let chi2: f64 = histogram.iter()
+            .map(|&n| (n as f64 - expected).powi(2) / expected)
+            .sum();
+        assert!(chi2 < bound, "chi-square {:.1} above {:.1}", chi2, bound);
+
+        let estimate = inv_pdf_sum / SAMPLES as f64;
+        assert!(
+            (estimate - measure as f64).abs() < 0.01 * measure as f64,
+            "measure {:.4}, expected {:.4}", estimate, measure,
+        );
+    }
+
+    #[test]
+    fn uniform_sphere_is_uniform() {
+        check_warp(uniform_sphere, |v| [(v.z + 1.0) / 2.0, phi(v)], |_| uniform_sphere_pdf(), 4.0 * PI);
+    }
+
+    #[test]
+    fn uniform_hemisphere_is_uniform() {
+        check_warp(uniform_hemisphere, |v| [v.z, phi(v)], |_| uniform_hemisphere_pdf(), 2.0 * PI);
+    }
+
+    #[test]
+    fn cosine_hemisphere_follows_cosine() {
+        check_warp(cosine_hemisphere, |v| [v.z * v.z, phi(v)], |v| cosine_hemisphere_pdf(v.z), 2.0 * PI);
+    }
+
+    #[test]
+    fn uniform_cone_is_uniform() {
+        check_warp(|u| uniform_cone(u, 0.5), |v| [(1.0 - v.z) / 0.5, phi(v)], |_| uniform_cone_pdf(0.5), PI);
+    }
+
+    #[test]
+    fn uniform_disk_is_uniform() {
+        check_warp(uniform_disk, |v| [v.x * v.x + v.y * v.y, phi(v)], |_| uniform_disk_pdf(), PI);
+    }
+
+    #[test]
+    fn concentric_disk_is_uniform() {
+        check_warp(concentric_disk, |v| [v.x * v.x + v.y * v.y, phi(v)], |_| uniform_disk_pdf(), PI);
+    }
+}
@@ -0,0 +1,861 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::vector::{Point3, Vec3, Color};
+use crate::objects::*;
+use crate::lights::{DirectionalLight, Light, PointLight, SpotLight};
+use crate::environment::{Background, EnvironmentMap};
+use crate::sky::PhysicalSky;
+use crate::noise::Perlin;
+use crate::bump::{BumpMap, NormalMap};
+use crate::coated::CoatedDiffuse;
+use crate::conductor::{Conductor, MetalPreset};
+use crate::glass::{absorption_for_tint, RoughDielectric, ThinDielectric};
+use crate::principled::Principled;
+use crate::mix::{Mask, MixMaterial, MixMode};
+use crate::texture::*;
+use crate::triangle::{Triangle, TriangleMesh};
+use crate::obj;
+
+/// Scene loaded from a TOML description, e.g.:
+///
+/// ```toml
+/// background = [0.0, 0.0, 0.0]  # defaults to the sky gradient
+/// # or a table: {type = "map", path = "sky.hdr", rotation = 90.0}
+/// # or {type = "physical", sun_elevation = 30.0, turbidity = 3.0}
+///
+/// [camera]
+/// lookfrom = [13.0, 2.0, 3.0]
+/// lookat = [0.0, 0.0, 0.0]
+///
+/// [textures.tiles]
+/// type = "checker"
+/// even = [0.9, 0.9, 0.9]
+/// odd = [0.2, 0.3, 0.1]
+///
+/// [materials.ground]
+/// type = "lambertian"
+/// albedo = "tiles"  # a colour, a number or a texture name
+///
+/// [[objects]]
+/// type = "sphere"
+/// centre = [0.0, -1000.0, 0.0]
+/// radius = 1000.0
+/// material = "ground"
+///
+/// [[lights]]
+/// type = "point"
+/// position = [0.0, 5.0, 0.0]
+/// intensity = [20.0, 20.0, 20.0]
+/// ```
+pub struct SceneFile {
+    pub camera: CameraOverrides,
+    pub scene: Scene,
+    /// Problems in referenced models that did not stop the scene loading
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub dist_to_focus: f32,
+}
+
+/// Camera fields given in a scene file, the others keep their value from the
+/// command line.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraOverrides {
+    #[serde(with = "optional_vec3")]
+    pub lookfrom: Option<Point3>,
+    #[serde(with = "optional_vec3")]
+    pub lookat: Option<Point3>,
+    #[serde(with = "optional_vec3")]
+    pub vup: Option<Vec3>,
+    pub vfov: Option<f32>,
+    pub aperture: Option<f32>,
+    pub dist_to_focus: Option<f32>,
+}
+
+impl CameraOverrides {
+    pub fn apply(&self, settings: CameraSettings) -> CameraSettings {
+        CameraSettings {
+            lookfrom: self.lookfrom.unwrap_or(settings.lookfrom),
+            lookat: self.lookat.unwrap_or(settings.lookat),
+            vup: self.vup.unwrap_or(settings.vup),
+            vfov: self.vfov.unwrap_or(settings.vfov),
+            aperture: self.aperture.unwrap_or(settings.aperture),
+            dist_to_focus: self.dist_to_focus.unwrap_or(settings.dist_to_focus),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SceneFileError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SceneFileError {
+    fn at(source: &str, offset: usize, message: String) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        Self {message, line, column}
+    }
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for SceneFileError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawScene {
+    #[serde(default)]
+    camera: CameraOverrides,
+    /// A plain colour or a `RawEnvironment` table
+    background: Option<Spanned<toml::Value>>,
+    #[serde(default)]
+    textures: HashMap<String, Spanned<RawTexture>>,
+    #[serde(default)]
+    materials: HashMap<String, Spanned<RawMaterial>>,
+    #[serde(default)]
+    objects: Vec<Spanned<RawObject>>,
+    #[serde(default)]
+    lights: Vec<Spanned<RawLight>>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawEnvironment {
+    Solid {
+        #[serde(with = "vec3")]
+        color: Color,
+    },
+    Gradient {
+        #[serde(with = "vec3")]
+        bottom: Color,
+        #[serde(with = "vec3")]
+        top: Color,
+    },
+    Map {
+        path: String,
+        /// Degrees around the vertical axis
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+    /// Physical sky, also adds the matching sun
+    Physical {
+        /// Degrees above the horizon
+        sun_elevation: f32,
+        /// Degrees clockwise from -z seen from above
+        #[serde(default)]
+        sun_azimuth: f32,
+        #[serde(default = "default_turbidity")]
+        turbidity: f32,
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+}
+
+fn default_turbidity() -> f32 {
+    3.0
+}
+
+/// Material parameter given as a colour, a grey value or a texture name.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "toml::Value")]
+enum RawTextureRef {
+    Color(Color),
+    Name(String),
+}
+
+impl TryFrom<toml::Value> for RawTextureRef {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<Self, String> {
+        let error = || "expected a colour, a number or a texture name".to_string();
+        let grey = |v: f32| RawTextureRef::Color(Color::new(v, v, v));
+        match value {
+            toml::Value::String(name) => Ok(RawTextureRef::Name(name)),
+            toml::Value::Float(v) => Ok(grey(v as f32)),
+            toml::Value::Integer(v) => Ok(grey(v as f32)),
+            value @ toml::Value::Array(_) => value.try_into::<[f32; 3]>()
+                .map(|c| RawTextureRef::Color(Color::from(c)))
+                .map_err(|_| error()),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawTexture {
+    Solid {
+        #[serde(with = "vec3")]
+        color: Color,
+    },
+    Checker {
+        even: RawTextureRef,
+        odd: RawTextureRef,
+        /// Side of each cube
+        #[serde(default = "one")]
+        scale: f32,
+    },
+    Image {
+        path: String,
+        #[serde(default)]
+        wrap: WrapMode,
+        /// Unset for data such as normal or height maps
+        #[serde(default = "yes")]
+        srgb: bool,
+    },
+    Noise {
+        #[serde(with = "vec3")]
+        low: Color,
+        #[serde(with = "vec3")]
+        high: Color,
+        #[serde(default = "one")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default)]
+        seed: u64,
+    },
+    Marble {
+        #[serde(with = "vec3")]
+        base: Color,
+        #[serde(with = "vec3")]
+        vein: Color,
+        #[serde(default = "one")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_marble_distortion")]
+        distortion: f32,
+        #[serde(default)]
+        seed: u64,
+    },
+    Wood {
+        #[serde(with = "vec3")]
+        light: Color,
+        #[serde(with = "vec3")]
+        dark: Color,
+        #[serde(default = "one")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "one")]
+        distortion: f32,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+fn default_octaves() -> u32 {
+    4
+}
+
+fn default_marble_distortion() -> f32 {
+    5.0
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawMaterial {
+    Lambertian {
+        albedo: RawTextureRef,
+    },
+    Metal {
+        albedo: RawTextureRef,
+        #[serde(default = "zero_texture")]
+        fuzz: RawTextureRef,
+    },
+    /// GGX metal, either a preset `metal` or a complex index `eta` + i `k`
+    Conductor {
+        metal: Option<MetalPreset>,
+        eta: Option<[f32; 3]>,
+        k: Option<[f32; 3]>,
+        #[serde(default = "zero_texture")]
+        roughness: RawTextureRef,
+        /// Anisotropic roughness along u and v, default to `roughness`
+        roughness_u: Option<RawTextureRef>,
+        roughness_v: Option<RawTextureRef>,
+    },
+    /// Glass, frosted when `roughness` is given, tinted when `tint` is
+    Dielectric {
+        index: f32,
+        roughness: Option<RawTextureRef>,
+        /// Colour left after `tint_distance` inside the glass
+        tint: Option<[f32; 3]>,
+        #[serde(default = "one")]
+        tint_distance: f32,
+    },
+    /// Diffuse base under a clear coat, plastics and varnish
+    CoatedDiffuse {
+        albedo: RawTextureRef,
+        #[serde(default = "default_coat_index")]
+        index: f32,
+        #[serde(default = "zero_texture")]
+        roughness: RawTextureRef,
+    },
+    /// Disney-style uber material, unset parameters keep their defaults
+    Principled {
+        base_color: RawTextureRef,
+        metallic: Option<RawTextureRef>,
+        roughness: Option<RawTextureRef>,
+        specular: Option<RawTextureRef>,
+        sheen: Option<RawTextureRef>,
+        clearcoat: Option<RawTextureRef>,
+        clearcoat_roughness: Option<f32>,
+        transmission: Option<RawTextureRef>,
+        index: Option<f32>,
+        emission: Option<RawTextureRef>,
+    },
+    /// Thin-walled glass for windows, light passes without refracting
+    ThinDielectric {
+        index: f32,
+        tint: Option<[f32; 3]>,
+    },
+    DiffuseLight {
+        emit: RawTextureRef,
+    },
+    /// Tangent space normal map over another material
+    NormalMap {
+        base: String,
+        map: RawTextureRef,
+        #[serde(default = "one")]
+        strength: f32,
+    },
+    /// Two materials blended by `amount`
+    Mix {
+        a: String,
+        b: String,
+        #[serde(default = "half_texture")]
+        amount: RawTextureRef,
+        #[serde(default)]
+        mode: MixMode,
+    },
+    /// Another material with holes where `opacity` is 0
+    Mask {
+        base: String,
+        opacity: RawTextureRef,
+    },
+    /// Height map over another material
+    BumpMap {
+        base: String,
+        height: RawTextureRef,
+        #[serde(default = "one")]
+        scale: f32,
+    },
+}
+
+fn default_coat_index() -> f32 {
+    1.5
+}
+
+fn half_texture() -> RawTextureRef {
+    RawTextureRef::Color(Color::new(0.5, 0.5, 0.5))
+}
+
+fn zero_texture() -> RawTextureRef {
+    RawTextureRef::Color(Color::new(0.0, 0.0, 0.0))
+}
+
+/// Builds named textures on first use, so they can refer to each other in
+/// any order.
+struct TextureBuilder<'a> {
+    source: &'a str,
+    base_dir: &'a Path,
+    raw: &'a HashMap<String, Spanned<RawTexture>>,
+    built: HashMap<String, Arc<dyn Texture + Send + Sync>>,
+    /// Textures being built, to catch cycles
+    pending: Vec<String>,
+}
+
+impl TextureBuilder<'_> {
+    /// Resolves a reference made from the item starting at `offset`.
+    fn get(
+        &mut self,
+        texture: &RawTextureRef,
+        offset: usize,
+    ) -> Result<Arc<dyn Texture + Send + Sync>, SceneFileError> {
+        let name = match texture {
+            RawTextureRef::Color(color) => return Ok(Arc::new(SolidColor::new(*color))),
+            RawTextureRef::Name(name) => name,
+        };
+        if let Some(texture) = self.built.get(name) {
+            return Ok(Arc::clone(texture));
+        }
+        let raw = self.raw.get(name).ok_or_else(|| {
+            SceneFileError::at(self.source, offset, format!("unknown texture '{}'", name))
+        })?;
+        let offset = raw.span().start;
+        if self.pending.contains(name) {
+            let message = format!("texture '{}' refers to itself", name);
+            return Err(SceneFileError::at(self.source, offset, message));
+        }
+
+        self.pending.push(name.clone());
+        let texture: Arc<dyn Texture + Send + Sync> = match raw.get_ref() {
+            RawTexture::Solid {color} => Arc::new(SolidColor::new(*color)),
+            RawTexture::Checker {even, odd, scale} => {
+                Arc::new(Checker::new(self.get(even, offset)?, self.get(odd, offset)?, *scale))
+            },
+            RawTexture::Image {path, wrap, srgb} => {
+                let image = ImageTexture::load(&self.base_dir.join(path), *wrap, *srgb)
+                    .map_err(|e| SceneFileError::at(self.source, offset, e.to_string()))?;
+                Arc::new(image)
+            },
+            RawTexture::Noise {low, high, scale, octaves, seed} => Arc::new(NoiseTexture {
+                noise: Perlin::new(*seed),
+                low: *low,
+                high: *high,
+                scale: *scale,
+                octaves: *octaves,
+            }),
+            RawTexture::Marble {base, vein, scale, octaves, distortion, seed} => Arc::new(Marble {
+                noise: Perlin::new(*seed),
+                base: *base,
+                vein: *vein,
+                scale: *scale,
+                octaves: *octaves,
+                distortion: *distortion,
+            }),
+            RawTexture::Wood {light, dark, scale, octaves, distortion, seed} => Arc::new(Wood {
+                noise: Perlin::new(*seed),
+                light: *light,
+                dark: *dark,
+                scale: *scale,
+                octaves: *octaves,
+                distortion: *distortion,
+            }),
+        };
+        self.pending.pop();
+        self.built.insert(name.clone(), Arc::clone(&texture));
+        Ok(texture)
+    }
+}
+
+/// Seed of the random choices of a named material, the same on every load.
+fn material_seed(name: &str) -> u64 {
+    name.bytes().fold(0, |h: u64, b| h.wrapping_mul(31).wrapping_add(b as u64))
+}
+
+/// Builds named materials on first use, like `TextureBuilder`, for materials
+/// wrapping other materials.
+struct MaterialBuilder<'a> {
+    source: &'a str,
+    raw: &'a HashMap<String, Spanned<RawMaterial>>,
+    textures: TextureBuilder<'a>,
+    built: HashMap<String, Arc<dyn Material + Send + Sync>>,
+    pending: Vec<String>,
+}
+
+impl MaterialBuilder<'_> {
+    fn get(&mut self, name: &str, offset: usize) -> Result<Arc<dyn Material + Send + Sync>, SceneFileError> {
+        if let Some(material) = self.built.get(name) {
+            return Ok(Arc::clone(material));
+        }
+        let raw = self.raw.get(name).ok_or_else(|| {
+            SceneFileError::at(self.source, offset, format!("unknown material '{}'", name))
+        })?;
+        let offset = raw.span().start;
+        if self.pending.iter().any(|pending| pending == name) {
+            let message = format!("material '{}' refers to itself", name);
+            return Err(SceneFileError::at(self.source, offset, message));
+        }
+
+        self.pending.push(name.to_string());
+        let textures = &mut self.textures;
+        let material: Arc<dyn Material + Send + Sync> = match raw.get_ref() {
+            RawMaterial::Lambertian {albedo} => {
+                Arc::new(Lambertian::textured(textures.get(albedo, offset)?))
+            },
+            RawMaterial::Metal {albedo, fuzz} => {
+                Arc::new(Metal::textured(textures.get(albedo, offset)?, textures.get(fuzz, offset)?))
+            },
+            RawMaterial::Conductor {metal, eta, k, roughness, roughness_u, roughness_v} => {
+                let (eta, k) = match (metal, eta, k) {
+                    (Some(metal), None, None) => metal.eta_k(),
+                    (None, Some(eta), Some(k)) => (Color::from(*eta), Color::from(*k)),
+                    _ => {
+                        let message = "conductor needs either `metal` or both `eta` and `k`";
+                        return Err(SceneFileError::at(self.source, offset, message.into()));
+                    },
+                };
+                let roughness_u = textures.get(roughness_u.as_ref().unwrap_or(roughness), offset)?;
+                let roughness_v = textures.get(roughness_v.as_ref().unwrap_or(roughness), offset)?;
+                Arc::new(Conductor::textured(eta, k, roughness_u, roughness_v))
+            },
+            RawMaterial::Dielectric {index, roughness, tint, tint_distance} => {
+                let absorption = tint.map_or(Color::new(0.0, 0.0, 0.0), |tint| {
+                    absorption_for_tint(&Color::from(tint), *tint_distance)
+                });
+                match roughness {
+                    Some(roughness) => Arc::new(RoughDielectric {
+                        absorption,
+                        ..RoughDielectric::textured(*index, textures.get(roughness, offset)?)
+                    }),
+                    None => Arc::new(Dielectric {absorption, ..Dielectric::new(*index)}),
+                }
+            },
+            RawMaterial::CoatedDiffuse {albedo, index, roughness} => {
+                let albedo = textures.get(albedo, offset)?;
+                Arc::new(CoatedDiffuse::textured(albedo, *index, textures.get(roughness, offset)?))
+            },
+            RawMaterial::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                clearcoat_roughness,
+                transmission,
+                index,
+                emission,
+            } => {
+                let mut mat = Principled::textured(textures.get(base_color, offset)?);
+                for (field, raw) in [
+                    (&mut mat.metallic, metallic),
+                    (&mut mat.roughness, roughness),
+                    (&mut mat.specular, specular),
+                    (&mut mat.sheen, sheen),
+                    (&mut mat.clearcoat, clearcoat),
+                    (&mut mat.transmission, transmission),
+                ] {
+                    if let Some(raw) = raw {
+                        *field = textures.get(raw, offset)?;
+                    }
+                }
+                mat.clearcoat_roughness = clearcoat_roughness.unwrap_or(mat.clearcoat_roughness);
+                mat.index = index.unwrap_or(mat.index);
+                mat.emission = emission.as_ref().map(|e| textures.get(e, offset)).transpose()?;
+                Arc::new(mat)
+            },
+            RawMaterial::ThinDielectric {index, tint} => {
+                let tint = tint.map_or(Color::new(1.0, 1.0, 1.0), Color::from);
+                Arc::new(ThinDielectric::new(*index, tint))
+            },
+            RawMaterial::DiffuseLight {emit} => {
+                Arc::new(DiffuseLight::textured(textures.get(emit, offset)?))
+            },
+            RawMaterial::NormalMap {base, map, strength} => {
+                let map = textures.get(map, offset)?;
+                Arc::new(NormalMap::new(self.get(base, offset)?, map, *strength))
+            },
+            RawMaterial::Mix {a, b, amount, mode} => {
+                let amount = textures.get(amount, offset)?;
+                let (a, b) = (self.get(a, offset)?, self.get(b, offset)?);
+                let mut mat = MixMaterial::textured(a, b, amount, *mode);
+                mat.seed = material_seed(name);
+                Arc::new(mat)
+            },
+            RawMaterial::Mask {base, opacity} => {
+                let opacity = textures.get(opacity, offset)?;
+                let mut mat = Mask::new(self.get(base, offset)?, opacity);
+                mat.seed = material_seed(name);
+                Arc::new(mat)
+            },
+            RawMaterial::BumpMap {base, height, scale} => {
+                let height = textures.get(height, offset)?;
+                Arc::new(BumpMap::new(self.get(base, offset)?, height, *scale))
+            },
+        };
+        self.pending.pop();
+        self.built.insert(name.to_string(), Arc::clone(&material));
+        Ok(material)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawObject {
+    Sphere {
+        #[serde(with = "vec3")]
+        centre: Point3,
+        radius: f32,
+        material: String,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        normals: Option<[[f32; 3]; 3]>,
+        material: String,
+    },
+    Mesh {
+        positions: Vec<[f32; 3]>,
+        normals: Option<Vec<[f32; 3]>>,
+        uvs: Option<Vec<[f32; 2]>>,
+        indices: Vec<[usize; 3]>,
+        material: String,
+    },
+    Model {
+        path: String,
+        /// Overrides the materials of the model's MTL files
+        material: Option<String>,
+        #[serde(default = "one")]
+        scale: f32,
+        #[serde(default)]
+        translate: [f32; 3],
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawLight {
+    Point {
+        #[serde(with = "vec3")]
+        position: Point3,
+        #[serde(with = "vec3")]
+        intensity: Color,
+    },
+    Spot {
+        #[serde(with = "vec3")]
+        position: Point3,
+        #[serde(with = "vec3")]
+        direction: Vec3,
+        #[serde(with = "vec3")]
+        intensity: Color,
+        /// Half angle of the cone in degrees
+        cone_angle: f32,
+        /// Angle where the falloff starts, a hard edge if not given
+        falloff_start: Option<f32>,
+    },
+    Directional {
+        /// Direction the light travels in
+        #[serde(with = "vec3")]
+        direction: Vec3,
+        #[serde(with = "vec3")]
+        irradiance: Color,
+    },
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn yes() -> bool {
+    true
+}
+
+mod vec3 {
+    use serde::{Deserialize, Deserializer};
+    use crate::vector::Vec3;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec3, D::Error> {
+        <[f32; 3]>::deserialize(d).map(Vec3::from)
+    }
+}
+
+mod optional_vec3 {
+    use serde::{Deserialize, Deserializer};
+    use crate::vector::Vec3;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec3>, D::Error> {
+        <Option<[f32; 3]>>::deserialize(d).map(|v| v.map(Vec3::from))
+    }
+}
+
+pub fn load(path: &Path) -> Result<SceneFile, Box<dyn Error>> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read scene file '{}': {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse(&source, base_dir).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Parses a scene description, relative model paths start at `base_dir`.
+pub fn parse(source: &str, base_dir: &Path) -> Result<SceneFile, SceneFileError> {
+    let raw: RawScene = toml::from_str(source).map_err(|e| {
+        let offset = e.span().map_or(0, |span| span.start);
+        SceneFileError::at(source, offset, e.message().to_string())
+    })?;
+
+    // Build every texture and material once so objects can share them
+    let textures = TextureBuilder {
+        source,
+        base_dir,
+        raw: &raw.textures,
+        built: HashMap::new(),
+        pending: vec![],
+    };
+    let mut materials = MaterialBuilder {
+        source,
+        raw: &raw.materials,
+        textures,
+        built: HashMap::new(),
+        pending: vec![],
+    };
+    for (name, mat) in raw.materials.iter() {
+        materials.get(name, mat.span().start)?;
+    }
+    let materials = materials.built;
+
+    let mut scene = Scene::new(vec![]);
+    let mut warnings = vec![];
+    if let Some(background) = raw.background {
+        let error = |message: String| SceneFileError::at(source, background.span().start, message);
+        let environment = match background.get_ref() {
+            toml::Value::Array(_) => background.get_ref().clone().try_into()
+                .map(|color: [f32; 3]| RawEnvironment::Solid {color: Color::from(color)}),
+            value => value.clone().try_into(),
+        }.map_err(|e| error(format!("invalid background: {}", e.message())))?;
+        match environment {
+            RawEnvironment::Solid {color} => scene.background = Background::Solid(color),
+            RawEnvironment::Gradient {bottom, top} => {
+                scene.background = Background::Gradient {bottom, top};
+            },
+            RawEnvironment::Map {path, rotation, intensity} => {
+                let map = EnvironmentMap::load(&base_dir.join(path), rotation, intensity)
+                    .map_err(|e| error(e.to_string()))?;
+                scene.background = Background::Map(map);
+            },
+            RawEnvironment::Physical {sun_elevation, sun_azimuth, turbidity, intensity} => {
+                scene.set_physical_sky(PhysicalSky::new(sun_elevation, sun_azimuth, turbidity, intensity));
+            },
+        }
+    }
+    for object in raw.objects.iter() {
+        let lookup = |name: &String| {
+            materials.get(name).cloned().ok_or_else(|| SceneFileError::at(
+                source,
+                object.span().start,
+                format!("unknown material '{}'", name),
+            ))
+        };
+
+        match object.get_ref() {
+            RawObject::Sphere {centre, radius, material} => {
+                scene.add(Arc::new(Sphere {
+                    centre: *centre,
+                    radius: *radius,
+                    mat: lookup(material)?,
+                }));
+            },
+            RawObject::Triangle {vertices, normals, material} => {
+                scene.add(Arc::new(Triangle::new(
+                    vertices.map(Vec3::from),
+                    normals.map(|n| n.map(Vec3::from)),
+                    lookup(material)?,
+                )));
+            },
+            RawObject::Mesh {positions, normals, uvs, indices, material} => {
+                let mesh = TriangleMesh::new(
+                    positions.iter().map(|&p| Vec3::from(p)).collect(),
+                    normals.as_ref().map(|n| n.iter().map(|&n| Vec3::from(n)).collect()),
+                    uvs.clone(),
+                    indices.clone(),
+                    lookup(material)?,
+                ).map_err(|e| SceneFileError::at(source, object.span().start, e))?;
+                scene.add_mesh(Arc::new(mesh));
+            },
+            RawObject::Model {path, material, scale, translate} => {
+                let material = material.as_ref().map(lookup).transpose()?;
+                let model = obj::load(&base_dir.join(path), material).map_err(|e| {
+                    SceneFileError::at(source, object.span().start, e.to_string())
+                })?;
+                warnings.extend(model.warnings);
+                for mut mesh in model.meshes {
+                    mesh.transform(*scale, Vec3::from(*translate));
+                    scene.add_mesh(Arc::new(mesh));
+                }
+            },
+        }
+    }
+
+    for light in raw.lights {
+        let error = |message: &str| SceneFileError::at(source, light.span().start, message.to_string());
+        let light: Arc<dyn Light + Send + Sync> = match *light.get_ref() {
+            RawLight::Point {position, intensity} => Arc::new(PointLight::new(position, intensity)),
+            RawLight::Spot {position, direction, intensity, cone_angle, falloff_start} => {
+                if direction.length_squared() == 0.0 {
+                    return Err(error("spot light direction must not be zero"));
+                }
+                if !(cone_angle > 0.0 && cone_angle <= 180.0) {
+                    return Err(error("cone_angle must be in (0, 180] degrees"));
+                }
+                let falloff_start = falloff_start.unwrap_or(cone_angle);
+                if falloff_start < 0.0 {
+                    return Err(error("falloff_start must not be negative"));
+                }
+                Arc::new(SpotLight::new(position, direction, intensity, cone_angle, falloff_start))
+            },
+            RawLight::Directional {direction, irradiance} => {
+                if direction.length_squared() == 0.0 {
+                    return Err(error("directional light direction must not be zero"));
+                }
+                Arc::new(DirectionalLight::new(direction, irradiance))
+            },
+        };
+        scene.add_light(light);
+    }
+
+    Ok(SceneFile {camera: raw.camera, scene, warnings})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_camera_keeps_other_settings() {
+        let file = parse("[camera]\nvfov = 50.0\n", Path::new("")).unwrap();
+        let cli = CameraSettings {
+            lookfrom: Point3::new(1.0, 2.0, 3.0),
+            lookat: Point3::new(0.0, 1.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 30.0,
+            aperture: 0.5,
+            dist_to_focus: 4.0,
+        };
+        let settings = file.camera.apply(cli);
+        assert_eq!(settings.vfov, 50.0);
+        assert_eq!(settings.lookfrom.x, cli.lookfrom.x);
+        assert_eq!(settings.aperture, cli.aperture);
+        assert_eq!(settings.dist_to_focus, cli.dist_to_focus);
+    }
+
+    #[test]
+    fn unknown_camera_field_is_reported() {
+        let e = parse("[camera]\nfov = 50.0\n", Path::new("")).err().unwrap();
+        assert_eq!(e.line, 2);
+    }
+
+    #[test]
+    fn invalid_light_is_reported_at_its_entry() {
+        let source = "\
+[[lights]]
+type = \"point\"
+position = [0.0, 5.0, 0.0]
+intensity = [1.0, 1.0, 1.0]
+
+[[lights]]
+type = \"spot\"
+position = [0.0, 5.0, 0.0]
+direction = [0.0, 0.0, 0.0]
+intensity = [1.0, 1.0, 1.0]
+cone_angle = 30.0
+";
+        let e = parse(source, Path::new("")).err().unwrap();
+        assert_eq!(e.line, 6);
+        assert!(e.to_string().contains("direction"), "{}", e);
+    }
+}
@@ -0,0 +1,150 @@
+//! Preetham, Shirley and Smits' analytic daylight model, "A Practical
+//! Analytic Model for Daylight" (1999).
+
+use crate::lights::DirectionalLight;
+use crate::utilities::{deg2rad, PI};
+use crate::vector::{Color, Vec3};
+
+/// Luminances come in kcd/m^2, scaled so a clear noon sky is around 0.3 and
+/// the sun's irradiance around 5.
+const SCALE: f32 = 0.05;
+
+/// Sun illuminance outside the atmosphere, in klx.
+const SOLAR_ILLUMINANCE: f32 = 128.0;
+
+/// Clear sky lit by the sun, which is left out of the dome and added as a
+/// separate directional light.
+pub struct PhysicalSky {
+    /// Unit direction towards the sun
+    pub sun_direction: Vec3,
+    pub turbidity: f32,
+    pub intensity: f32,
+    theta_sun: f32,
+    zenith: [f32; 3],
+    coeffs: [[f32; 5]; 3],
+}
+
+impl PhysicalSky {
+    /// `elevation` is the sun's angle above the horizon and `azimuth` its
+    /// angle clockwise from -z seen from above, both in degrees. `turbidity`
+    /// goes from 2 for a very clear sky to about 10 for a hazy one.
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32, intensity: f32) -> Self {
+        let elevation = deg2rad(elevation.clamp(0.0, 90.0));
+        let azimuth = deg2rad(azimuth);
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        let t = turbidity.clamp(1.7, 10.0);
+        let theta_sun = PI / 2.0 - elevation;
+
+        // Zenith luminance and chromaticity
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (th, th2, th3) = (theta_sun, theta_sun * theta_sun, theta_sun * theta_sun * theta_sun);
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_yc = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        // Perez distribution coefficients for Y, x and y
+        let coeffs = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        Self {
+            sun_direction,
+            turbidity: t,
+            intensity,
+            theta_sun,
+            zenith: [zenith_y.max(0.0), zenith_x, zenith_yc],
+            coeffs,
+        }
+    }
+
+    /// Radiance of the sky dome, directions below the horizon get the
+    /// radiance at the horizon.
+    pub fn radiance(&self, direction: &Vec3) -> Color {
+        let d = direction.normalize();
+        let cos_theta = d.y.max(0.001);
+        let gamma = d.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let perez = |c: &[f32; 5], cos_theta: f32, gamma: f32| {
+            (1.0 + c[0] * (c[1] / cos_theta).exp())
+                * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+        };
+        // Relative to the zenith, where the sun is theta_sun away
+        let [y, x, yc] = [0, 1, 2].map(|i| {
+            let c = &self.coeffs[i];
+            self.zenith[i] * perez(c, cos_theta, gamma) / perez(c, 1.0, self.theta_sun)
+        });
+        xyy_to_rgb(x, yc, y) * (SCALE * self.intensity)
+    }
+
+    /// The sun matching the sky, dimmed and reddened by the atmosphere.
+    pub fn sun(&self) -> DirectionalLight {
+        // Kasten and Young's relative optical air mass
+        let elevation_deg = 90.0 - self.theta_sun.to_degrees();
+        let air_mass = 1.0 / (self.theta_sun.cos() + 0.50572 * (elevation_deg + 6.07995).powf(-1.6364));
+
+        // Rayleigh and aerosol (Angstrom) transmittance at each channel's
+        // wavelength, in micrometres
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda: f32| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+            rayleigh * aerosol
+        };
+        let irradiance = Color::new(transmittance(0.65), transmittance(0.57), transmittance(0.475))
+            * (SOLAR_ILLUMINANCE * SCALE * self.intensity);
+        DirectionalLight::new(-self.sun_direction, irradiance)
+    }
+}
+
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Color {
+    if y <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    // XYZ to linear sRGB
+    Color::new(
+        (3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn luminance(c: &Color) -> f32 {
+        0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+    }
+
+    #[test]
+    fn zenith_radiance_is_zenith_luminance() {
+        for elevation in [0.0, 10.0, 45.0, 80.0] {
+            let sky = PhysicalSky::new(elevation, 30.0, 3.0, 1.0);
+            let zenith = luminance(&sky.radiance(&Vec3::new(0.0, 1.0, 0.0)));
+            let expected = sky.zenith[0] * SCALE;
+            assert!(zenith.is_finite() && zenith > 0.0, "elevation {}: {}", elevation, zenith);
+            assert!((zenith - expected).abs() < 1e-3 * expected, "elevation {}: {} vs {}", elevation, zenith, expected);
+        }
+    }
+
+    #[test]
+    fn sunset_sky_is_not_black() {
+        let sky = PhysicalSky::new(0.0, 0.0, 3.0, 1.0);
+        for direction in [Vec3::new(0.0, 0.2, -1.0), Vec3::new(1.0, 0.5, 0.0), Vec3::new(0.0, 0.1, 1.0)] {
+            let c = sky.radiance(&direction);
+            assert!(luminance(&c).is_finite() && luminance(&c) > 0.0, "{:?}", c);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Consistency checks shared by the tests of every material.
+
+use std::sync::Arc;
+use rand::Rng;
+
+use crate::objects::{HitRecord, Material};
+use crate::rng::SampleRng;
+use crate::sampling::{uniform_sphere, uniform_sphere_pdf};
+use crate::vector::{Color, Point3, Vec3};
+
+const SAMPLES: usize = 100_000;
+
+fn luminance(c: &Color) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+fn relative_error(a: f32, b: f32) -> f32 {
+    (a - b).abs() / a.abs().max(b.abs()).max(1e-3)
+}
+
+/// Running mean of a Monte Carlo estimator with its standard error.
+#[derive(Default)]
+struct Mean {
+    n: f64,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl Mean {
+    fn add(&mut self, x: f32) {
+        self.n += 1.0;
+        self.sum += x as f64;
+        self.sum_squares += (x * x) as f64;
+    }
+
+    fn mean(&self) -> f64 {
+        self.sum / self.n
+    }
+
+    fn standard_error(&self) -> f64 {
+        let mean = self.mean();
+        f64::sqrt(f64::max(0.0, self.sum_squares / self.n - mean * mean) / self.n)
+    }
+
+    /// Whether both estimate the same value, within five standard errors.
+    fn agrees(&self, other: &Mean) -> bool {
+        let error = f64::hypot(self.standard_error(), other.standard_error());
+        (self.mean() - other.mean()).abs() <= 5.0 * error + 0.002
+    }
+}
+
+/// Failures for one viewing direction, `front` tells whether the surface
+/// is seen from outside the object. Directions drawn with `sample` must
+/// report the same value and density as `eval` and `pdf`, the density
+/// must integrate to the fraction of samples that succeed, and the albedo
+/// estimated from the samples must match a uniform sphere estimate of the
+/// integral of `eval` and stay below one.
+fn check(mat: &Arc<dyn Material + Send + Sync>, wo: &Vec3, front: bool, rng: &mut SampleRng) -> Vec<String> {
+    let mut rec = HitRecord::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 1.0, mat.clone());
+    rec.front = front;
+    rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+    rec.dpdv = Vec3::new(0.0, 1.0, 0.0);
+    let mut failures = vec![];
+
+    // Sampled estimates, only non-specular samples can be compared to eval
+    let mut sampled_albedo = Mean::default();
+    let mut non_specular = Mean::default();
+    let mut mismatches = 0;
+    for _ in 0..SAMPLES {
+        let s = match mat.sample(wo, &rec, rng.gen(), [rng.gen(), rng.gen()]) {
+            Some(s) if !s.specular => s,
+            _ => {
+                sampled_albedo.add(0.0);
+                non_specular.add(0.0);
+                continue;
+            },
+        };
+        non_specular.add(1.0);
+        sampled_albedo.add(luminance(&s.weight(&rec.n)));
+        let pdf = mat.pdf(wo, &s.wi, &rec);
+        let f = luminance(&mat.eval(wo, &s.wi, &rec));
+        if relative_error(pdf, s.pdf) > 1e-3 || relative_error(f, luminance(&s.f)) > 1e-3 {
+            mismatches += 1;
+        }
+    }
+    if mismatches > SAMPLES / 1000 {
+        failures.push(format!("{} samples disagree with eval or pdf", mismatches));
+    }
+
+    // Uniform estimates of the pdf's integral and of the albedo
+    let mut pdf_integral = Mean::default();
+    let mut albedo = Mean::default();
+    for _ in 0..SAMPLES {
+        let wi = uniform_sphere([rng.gen(), rng.gen()]);
+        pdf_integral.add(mat.pdf(wo, &wi, &rec) / uniform_sphere_pdf());
+        albedo.add(luminance(&mat.eval(wo, &wi, &rec)) * wi.z.abs() / uniform_sphere_pdf());
+    }
+
+    if !pdf_integral.agrees(&non_specular) {
+        let (a, b) = (pdf_integral.mean(), non_specular.mean());
+        failures.push(format!("pdf integrates to {:.4}, {:.4} of samples succeed", a, b));
+    }
+    if !albedo.agrees(&sampled_albedo) {
+        let (a, b) = (albedo.mean(), sampled_albedo.mean());
+        failures.push(format!("albedo {:.4} by uniform sampling, {:.4} by importance sampling", a, b));
+    }
+    if sampled_albedo.mean() > 1.01 {
+        failures.push(format!("albedo {:.4} is above 1", sampled_albedo.mean()));
+    }
+    failures
+}
+
+/// Checks `mat` from both sides of the surface at a few angles, with a
+/// fixed seed so the outcome does not change between runs.
+pub(crate) fn check_bsdf(mat: Arc<dyn Material + Send + Sync>) {
+    let mut rng = SampleRng::seeded(7);
+    let mut failures = vec![];
+    for (front, side) in [(true, "outside"), (false, "inside")] {
+        for cos_theta in [0.95f32, 0.6, 0.2] {
+            let wo = Vec3::new(f32::sqrt(1.0 - cos_theta * cos_theta), 0.0, cos_theta);
+            for failure in check(&mat, &wo, front, &mut rng) {
+                failures.push(format!("{} at cos {:.2}: {}", side, cos_theta, failure));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,230 @@
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use serde::Deserialize;
+
+use crate::framebuffer::Framebuffer;
+use crate::image_io;
+use crate::noise::Perlin;
+use crate::utilities::PI;
+use crate::vector::{Color, Point3};
+
+/// Spatially varying colour, looked up with the surface coordinates and the
+/// hit point.
+pub trait Texture {
+    fn value(&self, u: f32, v: f32, p: &Point3) -> Color;
+
+    /// Number of texels along `u` and `v` for textures made of them, `None`
+    /// for those defined everywhere.
+    fn resolution(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+pub struct SolidColor {
+    pub color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Self {
+        Self {color}
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _p: &Point3) -> Color {
+        self.color
+    }
+}
+
+/// 3D checkerboard of cubes with side `scale`, works on any shape without
+/// needing texture coordinates.
+pub struct Checker {
+    pub even: Arc<dyn Texture + Send + Sync>,
+    pub odd: Arc<dyn Texture + Send + Sync>,
+    pub scale: f32,
+}
+
+impl Checker {
+    pub fn new(
+        even: Arc<dyn Texture + Send + Sync>,
+        odd: Arc<dyn Texture + Send + Sync>,
+        scale: f32,
+    ) -> Self {
+        Self {even, odd, scale}
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, p: &Point3) -> Color {
+        let cell = |x: f32| (x / self.scale).floor() as i64;
+        if (cell(p.x) + cell(p.y) + cell(p.z)).rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// How texture coordinates outside [0, 1] are brought back into the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    fn apply(&self, i: i64, len: usize) -> usize {
+        let len = len as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(len),
+            WrapMode::Clamp => i.clamp(0, len - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * len);
+                if i < len { i } else { 2 * len - 1 - i }
+            },
+        };
+        i as usize
+    }
+}
+
+/// Bilinearly filtered image, `v` goes up from the bottom row as in OBJ
+/// texture coordinates.
+pub struct ImageTexture {
+    pub image: Framebuffer<Color>,
+    pub wrap: WrapMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: Framebuffer<Color>, wrap: WrapMode) -> Self {
+        Self {image, wrap}
+    }
+
+    /// Loads a PNG, PPM or HDR image. Colour images are decoded from sRGB,
+    /// data such as normal or height maps should be loaded with `srgb` unset.
+    pub fn load(path: &Path, wrap: WrapMode, srgb: bool) -> Result<Self, Box<dyn Error>> {
+        let image = image_io::load_image(path, srgb)?;
+        if image.pixels.is_empty() {
+            return Err(format!("{}: empty image", path.display()).into());
+        }
+        Ok(Self::new(image, wrap))
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = self.wrap.apply(x, self.image.width);
+        let y = self.wrap.apply(y, self.image.height);
+        self.image.pixels[y * self.image.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn resolution(&self) -> Option<(usize, usize)> {
+        Some((self.image.width, self.image.height))
+    }
+
+    fn value(&self, u: f32, v: f32, _p: &Point3) -> Color {
+        // Texel centres sit at half-integer coordinates
+        let x = u * self.image.width as f32 - 0.5;
+        let y = (1.0 - v) * self.image.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.texel(x0, y0) * (1.0 - tx) + self.texel(x0 + 1, y0) * tx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - tx) + self.texel(x0 + 1, y0 + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+fn mix(a: Color, b: Color, t: f32) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+/// Blend of two colours driven by fBm noise.
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub low: Color,
+    pub high: Color,
+    /// Frequency of the first octave
+    pub scale: f32,
+    pub octaves: u32,
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Point3) -> Color {
+        let n = self.noise.fbm(&(*p * self.scale), self.octaves);
+        mix(self.low, self.high, (0.5 * (1.0 + n)).clamp(0.0, 1.0))
+    }
+}
+
+/// Veins along z, a sine wave bent by turbulence.
+pub struct Marble {
+    pub noise: Perlin,
+    pub base: Color,
+    pub vein: Color,
+    pub scale: f32,
+    pub octaves: u32,
+    /// How far the turbulence bends the veins
+    pub distortion: f32,
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f32, _v: f32, p: &Point3) -> Color {
+        let p = *p * self.scale;
+        let phase = p.z + self.distortion * self.noise.turbulence(&p, self.octaves);
+        mix(self.vein, self.base, 0.5 * (1.0 + phase.sin()))
+    }
+}
+
+/// Growth rings around the z axis, wobbled by fBm.
+pub struct Wood {
+    pub noise: Perlin,
+    pub light: Color,
+    pub dark: Color,
+    /// Rings per unit of distance from the axis
+    pub scale: f32,
+    pub octaves: u32,
+    pub distortion: f32,
+}
+
+impl Texture for Wood {
+    fn value(&self, _u: f32, _v: f32, p: &Point3) -> Color {
+        let radius = f32::sqrt(p.x * p.x + p.y * p.y) * self.scale;
+        let rings = radius + self.distortion * self.noise.fbm(&(*p * self.scale), self.octaves);
+        let t = 0.5 * (1.0 - f32::cos(2.0 * PI * rings.fract()));
+        mix(self.light, self.dark, t * t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::SampleRng;
+
+    /// Whether every channel of `c` lies between those of `a` and `b`.
+    fn between(c: Color, a: Color, b: Color) -> bool {
+        let inside = |c: f32, a: f32, b: f32| c >= a.min(b) - 1e-5 && c <= a.max(b) + 1e-5;
+        inside(c.x, a.x, b.x) && inside(c.y, a.y, b.y) && inside(c.z, a.z, b.z)
+    }
+
+    #[test]
+    fn procedural_textures_blend_their_colours() {
+        let (a, b) = (Color::new(0.9, 0.2, 0.1), Color::new(0.1, 0.3, 0.8));
+        let textures: Vec<(Box<dyn Texture>, &str)> = vec![
+            (Box::new(NoiseTexture {noise: Perlin::new(1), low: a, high: b, scale: 2.0, octaves: 4}), "noise"),
+            (Box::new(Marble {noise: Perlin::new(1), base: a, vein: b, scale: 2.0, octaves: 4, distortion: 5.0}), "marble"),
+            (Box::new(Wood {noise: Perlin::new(1), light: a, dark: b, scale: 3.0, octaves: 3, distortion: 0.5}), "wood"),
+        ];
+        let mut rng = SampleRng::seeded(9);
+        for _ in 0..10_000 {
+            let p = Point3::random(-20.0, 20.0, &mut rng);
+            for (texture, name) in textures.iter() {
+                let c = texture.value(0.0, 0.0, &p);
+                assert!(between(c, a, b), "{} gives {:?} at {:?}", name, c, p);
+                assert_eq!(c.x, texture.value(0.5, 0.5, &p).x, "{} depends on uv", name);
+            }
+        }
+    }
+}
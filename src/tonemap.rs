@@ -0,0 +1,145 @@
+use clap::ValueEnum;
+
+use crate::framebuffer::Framebuffer;
+use crate::utilities::clamp;
+use crate::vector::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ToneMapOperator {
+    /// Clip everything above 1.0
+    Clamp,
+    /// x / (1 + x) per channel
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TransferCurve {
+    /// Piecewise sRGB curve
+    Srgb,
+    /// Plain gamma 2.0 (square root)
+    Gamma2,
+    /// No encoding
+    Linear,
+}
+
+/// Turns linear radiance into display-ready 8-bit values.
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapper {
+    pub operator: ToneMapOperator,
+    /// Exposure compensation in stops
+    pub exposure: f32,
+    pub transfer: TransferCurve,
+}
+
+impl ToneMapper {
+    pub fn new(operator: ToneMapOperator, exposure: f32, transfer: TransferCurve) -> Self {
+        Self {operator, exposure, transfer}
+    }
+
+    pub fn map(&self, color: Color) -> [u8; 3] {
+        let color = color * f32::powf(2.0, self.exposure);
+        let quantize = |x: f32| {
+            let x = self.encode(self.compress(x));
+            (255.0 * clamp(x, 0.0, 1.0)).round() as u8
+        };
+        [quantize(color.x), quantize(color.y), quantize(color.z)]
+    }
+
+    pub fn apply(&self, image: &Framebuffer<Color>) -> Framebuffer<[u8; 3]> {
+        image.map(|&p| self.map(p))
+    }
+
+    fn compress(&self, x: f32) -> f32 {
+        let x = x.max(0.0);
+        match self.operator {
+            ToneMapOperator::Clamp => x.min(1.0),
+            ToneMapOperator::Reinhard => x / (1.0 + x),
+            ToneMapOperator::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0)
+            },
+        }
+    }
+
+    fn encode(&self, x: f32) -> f32 {
+        match self.transfer {
+            TransferCurve::Srgb => {
+                if x <= 0.0031308 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                }
+            },
+            TransferCurve::Gamma2 => x.sqrt(),
+            TransferCurve::Linear => x,
+        }
+    }
+}
+
+/// Inverse of the sRGB curve, for colours read from 8-bit images.
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self::new(ToneMapOperator::Clamp, 0.0, TransferCurve::Srgb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper(operator: ToneMapOperator) -> ToneMapper {
+        ToneMapper::new(operator, 0.0, TransferCurve::Srgb)
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        let srgb = mapper(ToneMapOperator::Clamp);
+        for i in 0..=1000 {
+            let x = i as f32 / 1000.0;
+            let y = srgb_to_linear(srgb.encode(x));
+            assert!((x - y).abs() < 1e-5, "{} comes back as {}", x, y);
+        }
+    }
+
+    #[test]
+    fn srgb_is_continuous_at_the_knee() {
+        let srgb = mapper(ToneMapOperator::Clamp);
+        let knee = 0.0031308f32;
+        let (below, above) = (srgb.encode(knee), srgb.encode(knee.next_up()));
+        assert!((below - above).abs() < 1e-5, "{} below the knee, {} above", below, above);
+        assert!((srgb_to_linear(0.04045) - srgb_to_linear(0.04045f32.next_up())).abs() < 1e-5);
+    }
+
+    #[test]
+    fn curves_are_monotonic_and_bounded() {
+        for operator in [ToneMapOperator::Reinhard, ToneMapOperator::Aces] {
+            let tm = mapper(operator);
+            let mut previous = tm.compress(0.0);
+            assert_eq!(previous, 0.0);
+            for i in 1..=10_000 {
+                let x = f32::powf(10.0, -4.0 + 8.0 * i as f32 / 10_000.0);
+                let y = tm.compress(x);
+                assert!((0.0..=1.0).contains(&y), "{:?} maps {} to {}", operator, x, y);
+                assert!(y >= previous, "{:?} decreases at {}", operator, x);
+                previous = y;
+            }
+            assert_eq!(tm.compress(-1.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let tm = ToneMapper::new(ToneMapOperator::Clamp, 1.0, TransferCurve::Linear);
+        assert_eq!(tm.map(Color::new(0.25, 0.5, 1.0)), [128, 255, 255]);
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+use crate::aabb::Aabb;
+use crate::objects::{HitRecord, Hittable, Material, ObjectId};
+use crate::ray::Ray;
+use crate::sampling;
+use crate::vector::{Point3, Vec3};
+
+/// Vertex buffers shared by all the triangles of a mesh.
+pub struct TriangleMesh {
+    pub positions: Vec<Point3>,
+    /// Per-vertex normals for smooth shading, flat shading if `None`
+    pub normals: Option<Vec<Vec3>>,
+    /// Per-vertex texture coordinates
+    pub uvs: Option<Vec<[f32; 2]>>,
+    pub indices: Vec<[usize; 3]>,
+    pub mat: Arc<dyn Material + Send + Sync>,
+}
+
+impl TriangleMesh {
+    /// Fails if an index is out of range or if the normals or texture
+    /// coordinates are not one per position.
+    pub fn new(
+        positions: Vec<Point3>,
+        normals: Option<Vec<Vec3>>,
+        uvs: Option<Vec<[f32; 2]>>,
+        indices: Vec<[usize; 3]>,
+        mat: Arc<dyn Material + Send + Sync>,
+    ) -> Result<Self, String> {
+        if let Some(i) = indices.iter().flatten().find(|&&i| i >= positions.len()) {
+            return Err(format!("mesh index {} out of range, there are {} positions", i, positions.len()));
+        }
+        if normals.as_ref().is_some_and(|n| n.len() != positions.len()) {
+            return Err("mesh needs one normal per position".to_string());
+        }
+        if uvs.as_ref().is_some_and(|uv| uv.len() != positions.len()) {
+            return Err("mesh needs one texture coordinate per position".to_string());
+        }
+        Ok(Self {positions, normals, uvs, indices, mat})
+    }
+
+    /// Scales and then moves every vertex.
+    pub fn transform(&mut self, scale: f32, translate: Vec3) {
+        for p in self.positions.iter_mut() {
+            *p = *p * scale + translate;
+        }
+    }
+
+    /// One hittable per face, all of them referencing this mesh.
+    pub fn triangles(self: &Arc<Self>) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        (0..self.indices.len())
+            .map(|face| {
+                Arc::new(Triangle {mesh: Arc::clone(self), face}) as Arc<dyn Hittable + Send + Sync>
+            })
+            .collect()
+    }
+}
+
+pub struct Triangle {
+    pub mesh: Arc<TriangleMesh>,
+    pub face: usize,
+}
+
+impl Triangle {
+    /// Standalone triangle, backed by a mesh of its own.
+    pub fn new(
+        vertices: [Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        mat: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        let mesh = TriangleMesh::new(
+            vertices.to_vec(),
+            normals.map(|n| n.to_vec()),
+            None,
+            vec![[0, 1, 2]],
+            mat,
+        ).expect("a single triangle has valid indices");
+        Self {mesh: Arc::new(mesh), face: 0}
+    }
+
+    fn vertices(&self) -> [Point3; 3] {
+        let [i0, i1, i2] = self.mesh.indices[self.face];
+        [self.mesh.positions[i0], self.mesh.positions[i1], self.mesh.positions[i2]]
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        // Möller-Trumbore
+        let [p0, p1, p2] = self.vertices();
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = r.direction.cross(&e2);
+        let det = e1.dot(&pvec);
+
+        // Relative to the edges so small triangles are not dropped
+        if det.abs() <= f32::EPSILON * e1.length() * e2.length() * r.direction.length() {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let tvec = r.origin - p0;
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = tvec.cross(&e1);
+        let v = r.direction.dot(&qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        // Interpolate the vertex normals, kept on the geometric side
+        let geometric = e1.cross(&e2).normalize();
+        let shading = match &self.mesh.normals {
+            Some(normals) => {
+                let [i0, i1, i2] = self.mesh.indices[self.face];
+                let n = (normals[i0] * (1.0 - u - v) + normals[i1] * u + normals[i2] * v).normalize();
+                if n.dot(&geometric) < 0.0 { -n } else { n }
+            },
+            None => geometric,
+        };
+
+        let front = r.direction.dot(&geometric) < 0.0;
+        let mut rec = HitRecord::new(
+            r.at(t),
+            if front { shading } else { -shading },
+            t,
+            Arc::clone(&self.mesh.mat) as Arc<dyn Material>,
+        );
+        rec.front = front;
+        rec.object = Some(ObjectId::of(self));
+
+        // Without texture coordinates the corners get (0, 0), (1, 0) and (1, 1)
+        let [i0, i1, i2] = self.mesh.indices[self.face];
+        let [uv0, uv1, uv2] = match &self.mesh.uvs {
+            Some(uvs) => [uvs[i0], uvs[i1], uvs[i2]],
+            None => [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]],
+        };
+        let w = 1.0 - u - v;
+        rec.u = w * uv0[0] + u * uv1[0] + v * uv2[0];
+        rec.v = w * uv0[1] + u * uv1[1] + v * uv2[1];
+
+        // Tangents from the texture coordinate differences, keep the default
+        // frame if they are degenerate
+        let (du1, dv1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+        let (du2, dv2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+        let det = du1 * dv2 - dv1 * du2;
+        if det.abs() > 1e-12 {
+            let dpdu = (e1 * dv2 - e2 * dv1) / det;
+            let dpdv = (e2 * du1 - e1 * du2) / det;
+            if !dpdu.cross(&dpdv).near_zero() {
+                (rec.dpdu, rec.dpdv) = (dpdu, dpdv);
+            }
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad the box so axis-aligned triangles do not end up flat
+        let [p0, p1, p2] = self.vertices();
+        let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+        let bbox = Aabb::new(p0, p0)
+            .surrounding(&Aabb::new(p1, p1))
+            .surrounding(&Aabb::new(p2, p2));
+        Some(Aabb::new(bbox.min - pad, bbox.max + pad))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mesh.mat.is_emissive()
+    }
+
+    fn sample_direction(&self, origin: &Point3, u: [f32; 2]) -> Option<Vec3> {
+        // Uniform over the area, converted to solid angle by `pdf_direction`
+        let [p0, p1, p2] = self.vertices();
+        let [b1, b2] = sampling::uniform_triangle(u);
+        let p = p0 * (1.0 - b1 - b2) + p1 * b1 + p2 * b2;
+        let direction = p - *origin;
+        if direction.near_zero() {
+            return None;
+        }
+        Some(direction.normalize())
+    }
+
+    fn pdf_direction(&self, origin: &Point3, direction: &Vec3) -> f32 {
+        let rec = match self.hit(&Ray::new(*origin, *direction), 0.001, f32::INFINITY) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        let [p0, p1, p2] = self.vertices();
+        let normal = (p1 - p0).cross(&(p2 - p0));
+        let area = 0.5 * normal.length();
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = normal.dot(direction).abs() / (2.0 * area * direction.length());
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        distance_squared / (cosine * area)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::Lambertian;
+    use crate::vector::Color;
+
+    fn grey() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn rejects_malformed_meshes() {
+        let positions = vec![Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)];
+        assert!(TriangleMesh::new(positions.clone(), None, None, vec![[0, 1, 3]], grey()).is_err());
+        let normals = Some(vec![Vec3::new(0.0, 0.0, 1.0)]);
+        assert!(TriangleMesh::new(positions.clone(), normals, None, vec![[0, 1, 2]], grey()).is_err());
+        let uvs = Some(vec![[0.0, 0.0]; 4]);
+        assert!(TriangleMesh::new(positions.clone(), None, uvs, vec![[0, 1, 2]], grey()).is_err());
+        assert!(TriangleMesh::new(positions, None, None, vec![[0, 1, 2]], grey()).is_ok());
+    }
+
+    #[test]
+    fn hits_small_triangles() {
+        let s = 1e-5;
+        let triangle = Triangle::new(
+            [Point3::new(0.0, 0.0, 0.0), Point3::new(s, 0.0, 0.0), Point3::new(0.0, s, 0.0)],
+            None,
+            grey(),
+        );
+        let r = Ray::new(Point3::new(0.25 * s, 0.25 * s, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = triangle.hit(&r, 0.001, f32::INFINITY).expect("small triangle missed");
+        assert!((rec.t - 1.0).abs() < 1e-5);
+    }
+}
@@ -1,4 +1,4 @@
-pub const PI: f32 = 3.1415926535897932385;
+pub const PI: f32 = std::f32::consts::PI;
 
 pub fn deg2rad(deg: f32) -> f32 {
     deg * PI / 180.0
@@ -1,6 +1,7 @@
 use std::ops;
 use std::fmt;
-use rand::{Rng, thread_rng};
+use rand::Rng;
+use crate::sampling;
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Vec3 {
@@ -42,37 +43,19 @@ impl Vec3 {
         }
     }
 
-    pub fn unit_random() -> Self {
-        let mut p: Vec3;
-        loop {
-            p = Vec3::random(-1.0, 1.0).normalize();
-            if p.length_squared() <= 1.0 {
-                break;
-            }
-        }
-        return p;
-    }
-
-    pub fn unit_disk_random() -> Self {
-        let mut p: Vec3;
-        loop {
-            p = Vec3::new(
-                thread_rng().gen::<f32>(),
-                thread_rng().gen::<f32>(),
-                0.0,
-            );
-            if p.length_squared() <= 1.0 {
-                break;
-            }
-        }
-        return p;
+    pub fn unit_random<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        sampling::uniform_sphere([rng.gen(), rng.gen()])
+    }
+
+    pub fn unit_disk_random<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        sampling::concentric_disk([rng.gen(), rng.gen()])
     }
 
-    pub fn random(min: f32, max: f32) -> Self {
+    pub fn random<R: Rng + ?Sized>(min: f32, max: f32, rng: &mut R) -> Self {
         Vec3::new(
-            thread_rng().gen_range(min..=max),
-            thread_rng().gen_range(min..=max),
-            thread_rng().gen_range(min..=max),
+            rng.gen_range(min..=max),
+            rng.gen_range(min..=max),
+            rng.gen_range(min..=max),
         )
     }
 
@@ -172,12 +155,30 @@ impl ops::Div<f32> for Vec3 {
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f32;
+    fn index(&self, axis: usize) -> &Self::Output {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl fmt::Display for Vec3 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} {} {}", self.x, self.y, self.z)
     }
 }
 
+impl From<[f32; 3]> for Vec3 {
+    fn from(v: [f32; 3]) -> Self {
+        Vec3::new(v[0], v[1], v[2])
+    }
+}
+
 impl<'s> From<&'s str> for Vec3 {
     fn from(s: &'s str) -> Self {
         let mut values = s.split(',');